@@ -0,0 +1,367 @@
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::io;
+use std::net::ToSocketAddrs;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+extern crate futures;
+use self::futures::{future, Async, Future, Poll, Stream};
+use self::futures::sync::{mpsc, oneshot};
+
+extern crate tokio_core;
+use self::tokio_core::net::TcpStream;
+use self::tokio_core::reactor::Handle;
+
+extern crate tokio_io;
+use self::tokio_io::AsyncRead;
+use self::tokio_io::io::{read_exact, write_all, ReadHalf};
+
+extern crate byteorder;
+use self::byteorder::{ByteOrder, LittleEndian};
+
+extern crate rustc_serialize;
+use self::rustc_serialize::json;
+
+use datum::Datum;
+use connection::{ConnectionError, SendError, encode_query, parse_response, serialize_params};
+use handshake::{Handshake, HandshakeStep};
+use query::{ReQL, RunQueryError};
+use response::RethinkResponse;
+
+use ql2::{Query_QueryType, Response_ResponseType};
+
+pub type ResponseFuture = Box<Future<Item = json::Json, Error = SendError>>;
+
+type ResponseSender = oneshot::Sender<Result<json::Json, SendError>>;
+
+// Queries waiting on a response, keyed by query token. Once the reader task stops, `error`
+// records why, and no more queries may be registered.
+struct InFlight {
+    senders: HashMap<u64, ResponseSender>,
+    error: Option<String>,
+}
+
+struct Shared {
+    query_count: AtomicUsize,
+    in_flight: Mutex<InFlight>,
+    frames: mpsc::UnboundedSender<Vec<u8>>,
+    default_db: Option<String>,
+    // Dropped along with the last handle to the connection, which stops the reader task
+    _closed: oneshot::Sender<()>,
+}
+
+impl Shared {
+    fn dispatch(&self, token: u64, response: Result<json::Json, SendError>) {
+        // Nobody may be waiting if the query was abandoned or stopped
+        if let Some(sender) = self.in_flight.lock().unwrap().senders.remove(&token) {
+            let _ = sender.send(response);
+        }
+    }
+
+    fn fail(&self, message: String) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        for (_, sender) in in_flight.senders.drain() {
+            let _ = sender.send(Err(SendError::IoError(message.clone())));
+        }
+        in_flight.error = Some(message);
+    }
+}
+
+/// A connection which runs on a tokio reactor instead of blocking the calling thread.
+///
+/// It speaks the same handshake and framing as `Connection`, but only over plaintext. For an
+/// encrypted connection, use a `Connection` with `use_tls`. Cloning an `AsyncConnection` is
+/// cheap, and every clone shares the same socket. The socket is closed once every clone, and
+/// every `AsyncCursor` run on it, has been dropped.
+#[derive(Clone)]
+pub struct AsyncConnection {
+    shared: Arc<Shared>,
+}
+
+impl AsyncConnection {
+    /// Connects as the admin user, with the auth key as the password.
+    pub fn connect(host: &str, port: u16, default_db: Option<&str>, auth_key: Option<&str>, handle: &Handle)
+        -> Box<Future<Item = AsyncConnection, Error = ConnectionError>> {
+        AsyncConnection::connect_with_user(host, port, default_db, "admin", auth_key.unwrap_or(""), handle)
+    }
+
+    pub fn connect_with_user(host: &str, port: u16, default_db: Option<&str>, user: &str, password: &str, handle: &Handle)
+        -> Box<Future<Item = AsyncConnection, Error = ConnectionError>> {
+        let addr = match (host, port).to_socket_addrs().map(|mut addrs| addrs.next()) {
+            Ok(Some(addr)) => addr,
+            Ok(None) => return Box::new(future::err(ConnectionError::ConnectionError(
+                format!("Could not resolve {}", host)))),
+            Err(e) => return Box::new(future::err(ConnectionError::ConnectionError(
+                e.description().into())))
+        };
+        let handshake = Handshake::new(user, password);
+        let default_db = default_db.map(|x| x.to_string());
+        let handle = handle.clone();
+
+        Box::new(TcpStream::connect(&addr, &handle)
+            .map_err(|e| ConnectionError::ConnectionError(e.description().into()))
+            .and_then(move |stream| run_handshake(stream, handshake))
+            .map(move |stream| AsyncConnection::start(stream, default_db, &handle)))
+    }
+
+    fn start(stream: TcpStream, default_db: Option<String>, handle: &Handle) -> AsyncConnection {
+        let (reader, writer) = stream.split();
+        let (frames, frame_receiver) = mpsc::unbounded();
+        let (closed, closed_receiver) = oneshot::channel::<()>();
+
+        let shared = Arc::new(Shared {
+            query_count: AtomicUsize::new(0),
+            in_flight: Mutex::new(InFlight {
+                senders: HashMap::new(),
+                error: None,
+            }),
+            frames: frames,
+            default_db: default_db,
+            _closed: closed,
+        });
+
+        // Queued frames are written one at a time, so concurrent queries can't interleave. The
+        // queue ends when the last handle is dropped, and the writing half goes with it.
+        handle.spawn(frame_receiver.fold(writer, |writer, frame| {
+            write_all(writer, frame).map(|(writer, _)| writer).map_err(|_| ())
+        }).map(|_| ()));
+
+        // The tasks only hold weak references, so they don't keep the connection alive
+        let reader_shared = Arc::downgrade(&shared);
+        let error_shared = reader_shared.clone();
+        let reading = future::loop_fn(reader, move |reader| {
+            let shared = reader_shared.clone();
+            read_response(reader).map(move |(reader, token, response)| {
+                if let Some(shared) = shared.upgrade() {
+                    shared.dispatch(token, response);
+                }
+                future::Loop::<(), ReadHalf<TcpStream>>::Continue(reader)
+            })
+        }).map_err(move |e| {
+            if let Some(shared) = error_shared.upgrade() {
+                shared.fail(e.description().to_string());
+            }
+        });
+        // Once the reading half is dropped too, the socket is closed
+        handle.spawn(reading.select2(closed_receiver).map(|_| ()).map_err(|_| ()));
+
+        AsyncConnection { shared: shared }
+    }
+
+    pub(crate) fn next_token(&self) -> u64 {
+        (self.shared.query_count.fetch_add(1, Ordering::SeqCst) + 1) as u64
+    }
+
+    pub fn send(&self, raw_string: &str) -> ResponseFuture {
+        let token = self.next_token();
+        self.send_with_token(token, raw_string)
+    }
+
+    pub(crate) fn send_with_token(&self, token: u64, raw_string: &str) -> ResponseFuture {
+        let (sender, receiver) = oneshot::channel();
+        {
+            let mut in_flight = self.shared.in_flight.lock().unwrap();
+            if let Some(ref message) = in_flight.error {
+                return Box::new(future::err(SendError::IoError(message.clone())));
+            }
+            in_flight.senders.insert(token, sender);
+        }
+
+        if let Err(e) = self.write_query(token, raw_string) {
+            self.shared.in_flight.lock().unwrap().senders.remove(&token);
+            return Box::new(future::err(e));
+        }
+
+        Box::new(receiver.then(|received| match received {
+            Ok(response) => response,
+            // The reader task went away without answering
+            Err(_) => Err(SendError::ClosedConnectionError)
+        }))
+    }
+
+    // Queues a query without waiting for its response
+    fn write_query(&self, token: u64, raw_string: &str) -> Result<(), SendError> {
+        self.shared.frames.unbounded_send(encode_query(token, raw_string)).map_err(|_| {
+            SendError::ClosedConnectionError
+        })
+    }
+
+    pub(crate) fn serialize_params(&self) -> String {
+        serialize_params(&self.shared.default_db, &[])
+    }
+
+    pub fn default_db(&self) -> &Option<String> {
+        &self.shared.default_db
+    }
+}
+
+fn run_handshake(stream: TcpStream, handshake: Handshake) -> Box<Future<Item = TcpStream, Error = ConnectionError>> {
+    let start = handshake.start();
+    Box::new(write_all(stream, start).map_err(ConnectionError::from).and_then(move |(stream, _)| {
+        future::loop_fn((stream, handshake), |(stream, mut handshake)| {
+            read_handshake_message(stream).and_then(move |(stream, message)| {
+                match handshake.receive(&message) {
+                    Ok(HandshakeStep::Send(bytes)) => Box::new(write_all(stream, bytes)
+                        .map_err(ConnectionError::from)
+                        .map(move |(stream, _)| future::Loop::Continue((stream, handshake))))
+                        as Box<Future<Item = _, Error = _>>,
+                    Ok(HandshakeStep::Wait) => Box::new(future::ok(future::Loop::Continue((stream, handshake)))),
+                    Ok(HandshakeStep::Done) => Box::new(future::ok(future::Loop::Break(stream))),
+                    Err(e) => Box::new(future::err(e))
+                }
+            })
+        })
+    }))
+}
+
+// The server sends null terminated strings during the handshake
+fn read_handshake_message(stream: TcpStream) -> Box<Future<Item = (TcpStream, String), Error = ConnectionError>> {
+    Box::new(future::loop_fn((stream, Vec::new()), |(stream, mut bytes)| {
+        read_exact(stream, [0u8; 1]).map(move |(stream, byte)| {
+            if byte[0] == 0 {
+                future::Loop::Break((stream, bytes))
+            } else {
+                bytes.push(byte[0]);
+                future::Loop::Continue((stream, bytes))
+            }
+        })
+    }).map_err(ConnectionError::from).and_then(|(stream, bytes)| {
+        match String::from_utf8(bytes) {
+            Ok(message) => Ok((stream, message)),
+            Err(e) => Err(ConnectionError::IoError(e.description().to_string()))
+        }
+    }))
+}
+
+// Read a single response frame: the query token and the response body
+fn read_response(reader: ReadHalf<TcpStream>)
+    -> Box<Future<Item = (ReadHalf<TcpStream>, u64, Result<json::Json, SendError>), Error = io::Error>> {
+    Box::new(read_exact(reader, [0u8; 12]).and_then(|(reader, header)| {
+        let token = LittleEndian::read_u64(&header[..8]);
+        let resp_len = LittleEndian::read_u32(&header[8..]);
+        read_exact(reader, vec![0u8; resp_len as usize]).map(move |(reader, body)| {
+            (reader, token, parse_response(&body))
+        })
+    }))
+}
+
+/// The result of `ReQL::run_async`.
+pub enum AsyncResponse {
+    /// The query returned a single value
+    Atom(Datum),
+    /// The query returned a sequence or a cursor
+    Stream(AsyncCursor),
+}
+
+/// The asynchronous counterpart of `Cursor`: a `Stream` of results which sends `CONTINUE` for
+/// each further batch, and `STOP` if it is dropped before the server has finished.
+pub struct AsyncCursor {
+    connection: AsyncConnection,
+    query: ReQL,
+    token: u64,
+    response_type: Response_ResponseType,
+    batch: VecDeque<Datum>,
+    pending: Option<ResponseFuture>,
+    complete: bool,
+}
+
+impl AsyncCursor {
+    pub(crate) fn start(connection: AsyncConnection, query: ReQL, raw_query: &str) -> Box<Future<Item = AsyncResponse, Error = RunQueryError>> {
+        let token = connection.next_token();
+        let failed_query = query.clone();
+        Box::new(connection.send_with_token(token, raw_query)
+            .map_err(RunQueryError::from)
+            .and_then(|json| RethinkResponse::from_json(json).map_err(RunQueryError::from))
+            .and_then(|response| response.into_result().map_err(RunQueryError::from))
+            .map_err(move |e| failed_query.attach_to(e))
+            .map(move |mut response| {
+                if response.response_type == Response_ResponseType::SUCCESS_ATOM {
+                    return AsyncResponse::Atom(response.result.pop().unwrap_or(Datum::Null));
+                }
+
+                let mut cursor = AsyncCursor {
+                    connection: connection,
+                    query: query,
+                    token: token,
+                    response_type: response.response_type,
+                    batch: VecDeque::new(),
+                    pending: None,
+                    complete: false,
+                };
+                cursor.accept(response);
+                AsyncResponse::Stream(cursor)
+            }))
+    }
+
+    /// The type of the most recent response received for this query.
+    pub fn response_type(&self) -> Response_ResponseType {
+        self.response_type
+    }
+
+    /// True once the server has sent the final batch.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    fn accept(&mut self, response: RethinkResponse) {
+        self.response_type = response.response_type;
+        self.complete = response.response_type != Response_ResponseType::SUCCESS_PARTIAL;
+        self.batch.extend(response.result.into_iter());
+    }
+}
+
+impl Stream for AsyncCursor {
+    type Item = Datum;
+    type Error = RunQueryError;
+
+    fn poll(&mut self) -> Poll<Option<Datum>, RunQueryError> {
+        loop {
+            if let Some(datum) = self.batch.pop_front() {
+                return Ok(Async::Ready(Some(datum)));
+            }
+
+            if self.pending.is_none() {
+                if self.complete {
+                    return Ok(Async::Ready(None));
+                }
+                self.pending = Some(self.connection.send_with_token(
+                    self.token, &format!("[{}]", Query_QueryType::CONTINUE as u32)));
+            }
+
+            let json = match self.pending.as_mut().unwrap().poll() {
+                Ok(Async::Ready(json)) => json,
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(e) => {
+                    // Don't keep asking for batches from a query that is in an unknown state
+                    self.pending = None;
+                    self.complete = true;
+                    return Err(self.query.attach_to(RunQueryError::from(e)));
+                }
+            };
+            self.pending = None;
+
+            let response = RethinkResponse::from_json(json).map_err(RunQueryError::from).and_then(|response| {
+                response.into_result().map_err(RunQueryError::from)
+            });
+            match response {
+                Ok(response) => self.accept(response),
+                Err(e) => {
+                    // The server has ended the query, or it is in an unknown state
+                    self.complete = true;
+                    return Err(self.query.attach_to(e));
+                }
+            }
+        }
+    }
+}
+
+impl Drop for AsyncCursor {
+    fn drop(&mut self) {
+        // Nobody is left to wait for the response, so it's dropped when it arrives
+        if !self.complete {
+            let _ = self.connection.write_query(
+                self.token, &format!("[{}]", Query_QueryType::STOP as u32));
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use connection::{Connection, ConnectionError, SendError};
+use cursor::Cursor;
+use datum::{Datum, DecodeOptions};
+use query::{ReQL, RunQueryError};
+use rethink::rethink;
+use tls::TlsOptions;
+
+wrapped_enum!{
+    #[derive(Debug)]
+    /// An error which can occur when running a query on a cluster
+    pub enum ClusterError {
+        /// No server in the cluster could be reached
+        ConnectionError(ConnectionError),
+        /// An error running the query
+        RunQueryError(RunQueryError)
+    }
+}
+
+// How long to leave a server alone after failing to reach it
+const RETRY_INTERVAL_SECS: u64 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+struct ServerAddress {
+    host: String,
+    port: u16,
+}
+
+struct Node {
+    // Known once the server has been discovered through server_status
+    name: Option<String>,
+    // Every address the server might be reached at
+    addresses: Vec<ServerAddress>,
+    connection: Option<Arc<Connection>>,
+    retry_after: Option<Instant>,
+}
+
+impl Node {
+    fn label(&self) -> String {
+        match self.name {
+            Some(ref name) => name.clone(),
+            None => format!("{}:{}", self.addresses[0].host, self.addresses[0].port)
+        }
+    }
+}
+
+/// Connections to every server in a cluster.
+///
+/// Starting from a list of seed servers, the rest of the cluster is discovered through the
+/// `rethinkdb.server_status` system table. Queries are spread over the servers in turn, and a
+/// server which fails is skipped until it can be reached again.
+pub struct Cluster {
+    default_db: Option<String>,
+    user: String,
+    password: String,
+    tls: Option<TlsOptions>,
+    timeout_secs: u32,
+    nodes: Mutex<Vec<Node>>,
+    next: AtomicUsize,
+}
+
+impl Cluster {
+    pub fn new(seeds: &[(&str, u16)], default_db: Option<&str>, user: &str, password: &str, timeout_secs: u32) -> Self {
+        Cluster {
+            default_db: default_db.map(|x| x.to_string()),
+            user: user.to_string(),
+            password: password.to_string(),
+            tls: None,
+            timeout_secs: timeout_secs,
+            nodes: Mutex::new(seeds.iter().map(|&(host, port)| Node {
+                name: None,
+                addresses: vec![ServerAddress { host: host.to_string(), port: port }],
+                connection: None,
+                retry_after: None,
+            }).collect()),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Encrypt connections to every server. Takes effect for connections opened afterwards.
+    pub fn use_tls(&mut self, tls: Option<TlsOptions>) {
+        self.tls = tls;
+    }
+
+    /// Connects to the seed servers and discovers the rest of the cluster.
+    pub fn connect(&self) -> Result<(), ClusterError> {
+        self.refresh()
+    }
+
+    /// Looks for servers which have joined the cluster since it was last checked.
+    pub fn refresh(&self) -> Result<(), ClusterError> {
+        let mut servers = Vec::new();
+        for row in try!(self.run_read(&rethink::db("rethinkdb").table("server_status"))) {
+            if let Some(server) = parse_server_status(&try!(row)) {
+                servers.push(server);
+            }
+        }
+
+        // Resolving addresses can be slow, so it's done before taking the lock which every query
+        // needs. Nodes are never removed, so their indexes stay valid.
+        let known = self.nodes.lock().unwrap().iter().map(|node| node.addresses.clone()).collect::<Vec<_>>();
+        let known = known.iter().map(|addresses| resolve(addresses)).collect::<Vec<_>>();
+        let servers = servers.into_iter().map(|(name, addresses)| {
+            let resolved = resolve(&addresses);
+            (name, addresses, resolved)
+        }).collect::<Vec<_>>();
+
+        let mut nodes = self.nodes.lock().unwrap();
+        for (name, addresses, resolved) in servers {
+            // Two lists of addresses refer to the same server if any of them resolve to the same
+            // socket address
+            let known = nodes.iter().position(|node| node.name.as_ref() == Some(&name)).or_else(|| {
+                known.iter().position(|addrs| addrs.iter().any(|addr| resolved.contains(addr)))
+            });
+            match known {
+                Some(index) => {
+                    nodes[index].name = Some(name);
+                    for address in addresses {
+                        if !nodes[index].addresses.contains(&address) {
+                            nodes[index].addresses.push(address);
+                        }
+                    }
+                },
+                None => nodes.push(Node {
+                    name: Some(name),
+                    addresses: addresses,
+                    connection: None,
+                    retry_after: None,
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// The names (or addresses, before discovery) of every known server, and whether each one
+    /// currently has an open connection.
+    pub fn servers(&self) -> Vec<(String, bool)> {
+        self.nodes.lock().unwrap().iter().map(|node| {
+            (node.label(), node.connection.as_ref().map_or(false, |connection| connection.is_connected()))
+        }).collect()
+    }
+
+    /// Runs the query on the next available server. `Cursor::server` tells which server that was.
+    ///
+    /// If a server can't be reached, the query moves on to the next one. If the server fails
+    /// while the query is in flight, it fails with `SendError::IndeterminateWriteError`.
+    pub fn run(&self, query: &ReQL) -> Result<Cursor<'static>, ClusterError> {
+        self.run_with(query, false)
+    }
+
+    /// Like `run`, for queries which only read, so they can be run again on another server if
+    /// their server fails while they are in flight.
+    pub fn run_read(&self, query: &ReQL) -> Result<Cursor<'static>, ClusterError> {
+        self.run_with(query, true)
+    }
+
+    fn run_with(&self, query: &ReQL, safe_read: bool) -> Result<Cursor<'static>, ClusterError> {
+        let attempts = self.nodes.lock().unwrap().len();
+        for _ in 0..attempts {
+            let (server, connection) = try!(self.checkout());
+            let raw_query = try!(query.serialize_query_for_connection(&connection).map_err(RunQueryError::from));
+            let timeout = connection.timeout();
+
+            match Cursor::start_shared(connection.clone(), server, query, &raw_query, timeout, safe_read, DecodeOptions::default()) {
+                Ok(cursor) => return Ok(cursor),
+                Err(RunQueryError::SendError(e)) => {
+                    let sent = match e {
+                        SendError::ClosedConnectionError => false,
+                        SendError::IoError(..) | SendError::ReconnectError(..) => true,
+                        // The server is still there, it just didn't like the query
+                        other => return Err(ClusterError::from(RunQueryError::from(other)))
+                    };
+                    self.mark_failed(&connection);
+                    if sent && !safe_read {
+                        return Err(ClusterError::from(RunQueryError::from(SendError::IndeterminateWriteError(
+                            format!("The server failed while the query was in flight ({:?}), so it may or may not have been applied", e)))));
+                    }
+                },
+                Err(e) => return Err(ClusterError::from(e))
+            }
+        }
+        Err(no_servers())
+    }
+
+    // Pick the next server in turn which is (or can be) connected to
+    fn checkout(&self) -> Result<(String, Arc<Connection>), ClusterError> {
+        let count = self.nodes.lock().unwrap().len();
+        let start = self.next.fetch_add(1, Ordering::SeqCst);
+        let mut last_error = None;
+
+        for i in 0..count {
+            let index = (start + i) % count;
+            let addresses = {
+                let nodes = self.nodes.lock().unwrap();
+                let node = &nodes[index];
+                if let Some(ref connection) = node.connection {
+                    return Ok((node.label(), connection.clone()));
+                }
+                if node.retry_after.map_or(false, |retry_after| Instant::now() < retry_after) {
+                    continue;
+                }
+                node.addresses.clone()
+            };
+
+            // Don't hold up other queries while connecting
+            match self.open(&addresses) {
+                Ok(connection) => {
+                    let connection = Arc::new(connection);
+                    let mut nodes = self.nodes.lock().unwrap();
+                    let node = &mut nodes[index];
+                    node.connection = Some(connection.clone());
+                    node.retry_after = None;
+                    return Ok((node.label(), connection));
+                },
+                Err(e) => {
+                    let mut nodes = self.nodes.lock().unwrap();
+                    nodes[index].retry_after = Some(Instant::now() + Duration::from_secs(RETRY_INTERVAL_SECS));
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.map(ClusterError::from).unwrap_or_else(no_servers))
+    }
+
+    fn open(&self, addresses: &[ServerAddress]) -> Result<Connection, ConnectionError> {
+        let mut last_error = ConnectionError::ConnectionError("The server has no addresses".into());
+        for address in addresses {
+            let default_db = self.default_db.as_ref().map(|db| &db[..]);
+            let mut connection = Connection::new_with_user(&address.host, address.port, default_db,
+                                                           &self.user, &self.password, self.timeout_secs);
+            connection.use_tls(self.tls.clone());
+            match connection.connect() {
+                Ok(()) => return Ok(connection),
+                Err(e) => last_error = e
+            }
+        }
+        Err(last_error)
+    }
+
+    fn mark_failed(&self, connection: &Arc<Connection>) {
+        let mut nodes = self.nodes.lock().unwrap();
+        for node in nodes.iter_mut() {
+            let failed = node.connection.as_ref().map_or(false, |c| Arc::ptr_eq(c, connection));
+            if failed {
+                node.connection = None;
+                node.retry_after = Some(Instant::now() + Duration::from_secs(RETRY_INTERVAL_SECS));
+            }
+        }
+    }
+}
+
+fn no_servers() -> ClusterError {
+    ClusterError::from(ConnectionError::ConnectionError(
+        "No server in the cluster could be reached".into()))
+}
+
+// Every socket address the addresses resolve to. Ones which don't resolve are left out.
+fn resolve(addresses: &[ServerAddress]) -> Vec<SocketAddr> {
+    addresses.iter().flat_map(|address| {
+        (&*address.host, address.port).to_socket_addrs().map(|addrs| addrs.collect::<Vec<_>>()).unwrap_or(Vec::new())
+    }).collect()
+}
+
+// Pull the server's name and driver addresses out of a row of rethinkdb.server_status
+fn parse_server_status(row: &Datum) -> Option<(String, Vec<ServerAddress>)> {
+    let status = match *row {
+        Datum::Object(ref status) => status,
+        _ => return None
+    };
+    let name = match status.get("name") {
+        Some(&Datum::String(ref name)) => name.clone(),
+        _ => return None
+    };
+    let network = match status.get("network") {
+        Some(&Datum::Object(ref network)) => network,
+        _ => return None
+    };
+    let port = match network.get("reql_port") {
+        Some(&Datum::Number(port)) => match port.as_u64() {
+            Some(port) if port <= u16::max_value() as u64 => port as u16,
+            _ => return None
+        },
+        _ => return None
+    };
+
+    // The canonical addresses are the ones other servers use to reach this one
+    let mut hosts = match network.get("canonical_addresses") {
+        Some(&Datum::Array(ref addresses)) => addresses.iter().filter_map(|address| match *address {
+            Datum::Object(ref address) => match address.get("host") {
+                Some(&Datum::String(ref host)) => Some(host.clone()),
+                _ => None
+            },
+            _ => None
+        }).collect::<Vec<_>>(),
+        _ => Vec::new()
+    };
+    if let Some(&Datum::String(ref hostname)) = network.get("hostname") {
+        hosts.push(hostname.clone());
+    }
+    if hosts.is_empty() {
+        return None;
+    }
+
+    Some((name, hosts.into_iter().map(|host| ServerAddress { host: host, port: port }).collect()))
+}
+
+#[test]
+fn parses_server_status() {
+    let row = Datum::from_str(r#"{
+        "id": "2fe5e3b3-0c2b-4d56-9a0a-b4a2e6c1f8bd",
+        "name": "db_1",
+        "network": {
+            "canonical_addresses": [{"host": "10.0.0.1", "port": 29015}, {"host": "::1", "port": 29015}],
+            "cluster_port": 29015,
+            "hostname": "db-1.internal",
+            "http_admin_port": 8080,
+            "reql_port": 28015
+        }
+    }"#);
+
+    let (name, addresses) = parse_server_status(&row).unwrap();
+    assert_eq!(name, "db_1");
+    assert_eq!(addresses, vec![
+        ServerAddress { host: "10.0.0.1".to_string(), port: 28015 },
+        ServerAddress { host: "::1".to_string(), port: 28015 },
+        ServerAddress { host: "db-1.internal".to_string(), port: 28015 },
+    ]);
+}
@@ -1,7 +1,13 @@
-use std::net::TcpStream;
+use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
 
 extern crate byteorder;
-use self::byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
+use self::byteorder::{ByteOrder, ReadBytesExt, LittleEndian};
 
 use std::io;
 use std::io::Read;
@@ -12,6 +18,22 @@ use std::string::FromUtf8Error;
 extern crate rustc_serialize;
 use self::rustc_serialize::json;
 
+extern crate rand;
+use self::rand::Rng;
+
+extern crate rustls;
+use self::rustls::ClientSession;
+use self::rustls::Stream as TlsStream;
+
+use datum::quote;
+use handshake::{Handshake, HandshakeStep};
+use query::RunQueryError;
+use response::RethinkResponse;
+use tls;
+use tls::TlsOptions;
+
+use ql2::{Query_QueryType, Response_ResponseType};
+
 wrapped_enum!{
     #[derive(Debug)]
     /// An error while trying to read a C string
@@ -26,21 +48,37 @@ wrapped_enum!{
 
 // Read a C string from a Read
 fn read_string<T: Read>(r : &mut T) -> Result<String, ReadStringError> {
-    let bytes = try!(r.bytes().take_while(|b| {
-        match *b {
-            Err(_) => false,
-            Ok(x) => x != 0,
+    let mut bytes = Vec::new();
+    for b in r.bytes() {
+        match try!(b) {
+            0 => break,
+            x => bytes.push(x)
         }
-    }).collect::<Result<Vec<u8>,_>>());
+    }
     let s = try!(String::from_utf8(bytes));
     Ok(s)
 }
 
+// Sockets with a timeout report it as either of these, depending on the platform
+fn is_timeout(e: &io::Error) -> bool {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => true,
+        _ => false
+    }
+}
+
 #[derive(Debug)]
 pub enum ConnectionError {
     ConnectionError(String),
     InvalidOperationError(String),
     RethinkError(String),
+    /// The server rejected the user's credentials, or failed to prove that it knows them
+    AuthenticationError(String),
+    /// TLS could not be set up, or the server's certificate was rejected
+    TlsError(String),
+    /// The server took longer than the connection's timeout to accept the connection or answer
+    /// the handshake
+    TimeoutError(String),
     IoError(String)
 }
 
@@ -53,20 +91,44 @@ impl From<byteorder::Error> for ConnectionError {
     }
 }
 
+impl From<io::Error> for ConnectionError {
+    fn from(e: io::Error) -> Self {
+        // rustls reports a failed handshake, such as an untrusted certificate, as an IO error
+        if let Some(tls_error) = e.get_ref().and_then(|inner| inner.downcast_ref::<rustls::TLSError>()) {
+            return ConnectionError::TlsError(tls_error.to_string());
+        }
+        if is_timeout(&e) {
+            ConnectionError::TimeoutError(e.description().to_string())
+        } else {
+            ConnectionError::IoError(e.description().to_string())
+        }
+    }
+}
 
 impl From<ReadStringError> for ConnectionError {
     fn from(e: ReadStringError) -> Self {
-        ConnectionError::IoError(match e {
-            ReadStringError::FromUtf8Error(ref inner) => inner.description(),
-            ReadStringError::IoError(ref inner) => inner.description()
-        }.to_string())
+        match e {
+            ReadStringError::FromUtf8Error(ref inner) => ConnectionError::IoError(inner.description().to_string()),
+            ReadStringError::IoError(inner) => ConnectionError::from(inner)
+        }
     }
 }
 
 #[derive(Debug)]
 pub enum SendError {
+    /// The connection was closed, or had already failed, so the query was never sent
     ClosedConnectionError,
+    /// The connection failed after a query which wasn't marked as a safe read was sent, so it
+    /// may or may not have been applied
+    IndeterminateWriteError(String),
+    /// The connection failed, and could not be reestablished under the reconnect policy
+    ReconnectError(ConnectionError),
+    /// No response arrived within the query's timeout. The driver asks the server to stop the
+    /// query, but it may already have had some effect.
+    TimeoutError,
     ResponseParseError(json::ParserError),
+    /// No longer produced: responses are routed to their queries by token, so a response for
+    /// another query is never mistaken for this one. Kept so that existing matches still compile.
     MismatchedQueryTokenError(String),
     IoError(String)
 }
@@ -82,59 +144,429 @@ impl From<byteorder::Error> for SendError {
 
 impl From<io::Error> for SendError {
     fn from(e: io::Error) -> Self {
-        SendError::IoError(e.description().to_string())
+        if is_timeout(&e) {
+            SendError::TimeoutError
+        } else {
+            SendError::IoError(e.description().to_string())
+        }
     }
 }
 
 use self::ConnectionState::*;
 #[derive(Debug)]
 pub enum ConnectionState {
-    Open(TcpStream),
+    Open(Arc<Transport>),
     Closed,
 }
 
+/// How a `Connection` recovers when its socket breaks.
+///
+/// Reconnection attempts are spaced out with exponential backoff, with a random jitter so that
+/// many clients don't all reconnect at the same moment.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// How many times to try to reconnect before giving up on a query, across all of its re-sends
+    pub max_attempts: u32,
+    /// How long to wait before the second attempt. The first attempt is made immediately.
+    pub initial_backoff: Duration,
+    /// The longest to wait between attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    // A random delay between half and all of the backoff for the given attempt
+    fn backoff(&self, attempt: u32) -> Duration {
+        let millis = |d: Duration| d.as_secs() * 1000 + (d.subsec_nanos() / 1_000_000) as u64;
+        let initial = millis(self.initial_backoff);
+        let max = millis(self.max_backoff);
+        let backoff = initial.checked_mul(1 << attempt.min(32)).unwrap_or(max).min(max);
+        Duration::from_millis(rand::thread_rng().gen_range(backoff / 2, backoff + 1))
+    }
+}
+
+type ResponseSender = Sender<Result<json::Json, SendError>>;
+
+// Queries waiting on a response, keyed by query token. Once the reader thread stops, `error`
+// records why, and no more queries may be registered.
+struct InFlight {
+    senders: HashMap<u64, ResponseSender>,
+    error: Option<String>,
+}
+
+/// An open socket to the server. Queries are written directly to the socket, while a
+/// background thread reads response frames and hands each one to the query with the matching
+/// token, so many queries can be in flight on one connection at a time.
+pub struct Transport {
+    socket: TcpStream,
+    writer: Mutex<Box<Write + Send>>,
+    in_flight: Arc<Mutex<InFlight>>,
+    reader: Option<thread::JoinHandle<()>>,
+}
+
+impl ::std::fmt::Debug for Transport {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "Transport")
+    }
+}
+
+impl Transport {
+    fn plain(stream: TcpStream) -> Result<Transport, ConnectionError> {
+        let reader = try!(stream.try_clone());
+        let writer = try!(stream.try_clone());
+        Ok(Transport::new(stream, Box::new(reader), Box::new(writer)))
+    }
+
+    fn tls(stream: TcpStream, session: ClientSession) -> Result<Transport, ConnectionError> {
+        let (reader, writer) = try!(tls::split(session, &stream));
+        Ok(Transport::new(stream, Box::new(reader), Box::new(writer)))
+    }
+
+    fn new(socket: TcpStream, reader: Box<Read + Send>, writer: Box<Write + Send>) -> Transport {
+        let in_flight = Arc::new(Mutex::new(InFlight {
+            senders: HashMap::new(),
+            error: None,
+        }));
+
+        let reader_in_flight = in_flight.clone();
+        let reader = thread::spawn(move || {
+            read_responses(reader, reader_in_flight)
+        });
+
+        Transport {
+            socket: socket,
+            writer: Mutex::new(writer),
+            in_flight: in_flight,
+            reader: Some(reader),
+        }
+    }
+
+    fn send(&self, token: u64, raw_string: &str, timeout: Option<Duration>) -> Result<json::Json, SendError> {
+        let (sender, receiver) = channel();
+        {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            if in_flight.error.is_some() {
+                return Err(SendError::ClosedConnectionError);
+            }
+            in_flight.senders.insert(token, sender);
+        }
+
+        if let Err(e) = self.write_query(token, raw_string) {
+            self.in_flight.lock().unwrap().senders.remove(&token);
+            return Err(e);
+        }
+
+        let response = match timeout {
+            Some(timeout) => receiver.recv_timeout(timeout).map_err(|e| match e {
+                RecvTimeoutError::Timeout => SendError::TimeoutError,
+                // The reader thread went away without answering
+                RecvTimeoutError::Disconnected => SendError::ClosedConnectionError
+            }),
+            None => receiver.recv().map_err(|_| SendError::ClosedConnectionError)
+        };
+
+        match response {
+            Ok(response) => response,
+            Err(e) => {
+                // A response which arrives after we've given up is dropped
+                self.in_flight.lock().unwrap().senders.remove(&token);
+                if let SendError::TimeoutError = e {
+                    // Otherwise the server would keep running the query for nobody
+                    let _ = self.write_query(token, &format!("[{}]", Query_QueryType::STOP as u32));
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn send_noreply(&self, token: u64, raw_string: &str) -> Result<(), SendError> {
+        if self.is_broken() {
+            return Err(SendError::ClosedConnectionError);
+        }
+        // Nothing is registered for the token, so any response would be dropped
+        self.write_query(token, raw_string)
+    }
+
+    // True once the socket has failed
+    fn is_broken(&self) -> bool {
+        self.in_flight.lock().unwrap().error.is_some()
+    }
+
+    // A write which fails or times out may have sent part of the frame, and the server would
+    // misread everything after it, so the transport is broken and nothing more can be sent
+    fn write_query(&self, token: u64, raw_string: &str) -> Result<(), SendError> {
+        // Build the whole frame first so concurrent writers can't interleave
+        let frame = encode_query(token, raw_string);
+        let mut stream = self.writer.lock().unwrap();
+        if let Err(e) = stream.write_all(&frame) {
+            let e = SendError::from(e);
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                if in_flight.error.is_none() {
+                    in_flight.error = Some(format!("{:?}", e));
+                }
+            }
+            // Queries still waiting on a response fail once the reader thread sees the socket close
+            let _ = self.socket.shutdown(Shutdown::Both);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Transport {
+    fn drop(&mut self) {
+        // Shutting down the socket unblocks the reader thread
+        let _ = self.socket.shutdown(Shutdown::Both);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+// A query frame: the query token, the length of the query and the query itself
+pub(crate) fn encode_query(token: u64, raw_string: &str) -> Vec<u8> {
+    let bytes = raw_string.as_bytes();
+    let mut frame = vec![0u8; 12];
+    LittleEndian::write_u64(&mut frame[..8], token);
+    LittleEndian::write_u32(&mut frame[8..], bytes.len() as u32);
+    frame.extend_from_slice(bytes);
+    frame
+}
+
+// A body which fails to parse only affects its own query
+pub(crate) fn parse_response(bytes: &[u8]) -> Result<json::Json, SendError> {
+    json::Json::from_reader(&mut &bytes[..]).map_err(|parser_error| {
+        SendError::ResponseParseError(parser_error)})
+}
+
+// The global optargs of a START query. `optargs` holds names and serialized values.
+pub(crate) fn serialize_params(default_db: &Option<String>, optargs: &[(&str, String)]) -> String {
+    let mut params = Vec::new();
+    if let Some(ref db_name) = *default_db {
+        params.push(format!(r##""db":[14,[{}]]"##, quote(db_name)));
+    }
+    for &(name, ref value) in optargs {
+        params.push(format!("{}:{}", quote(name), value));
+    }
+    format!("{{{}}}", params.join(","))
+}
+
+// Read a single response frame: the query token and the response body
+fn read_response<T: Read>(stream: &mut T) -> Result<(u64, Result<json::Json, SendError>), SendError> {
+    let token = try!(stream.read_u64::<LittleEndian>());
+    let resp_len = try!(stream.read_u32::<LittleEndian>());
+
+    let mut resp_bytes = Vec::with_capacity(resp_len as usize);
+    try!(Read::by_ref(stream).take(resp_len as u64).read_to_end(&mut resp_bytes));
+    if resp_bytes.len() != resp_len as usize {
+        return Err(SendError::IoError("Unexpected EOF".to_string()));
+    }
+
+    Ok((token, parse_response(&resp_bytes)))
+}
+
+// Answers each START query on `stream` with the query's own term, as if it had been run, until
+// the client hangs up. For servers standing in for RethinkDB in tests.
+#[cfg(test)]
+pub fn echo_queries<S: Read + Write>(stream: &mut S) {
+    while let Ok(token) = stream.read_u64::<LittleEndian>() {
+        let mut query = vec![0u8; match stream.read_u32::<LittleEndian>() { Ok(len) => len as usize, Err(_) => return }];
+        if stream.read_exact(&mut query).is_err() {
+            return;
+        }
+        let query = match json::Json::from_str(&String::from_utf8_lossy(&query)) { Ok(query) => query, Err(_) => return };
+        // Other kinds of query, such as STOP, go unanswered
+        if let Some(term) = query.as_array().and_then(|query| query.get(1)) {
+            if stream.write_all(&encode_query(token, &format!(r#"{{"t":1,"r":[{}]}}"#, term))).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn read_responses(mut stream: Box<Read + Send>, in_flight: Arc<Mutex<InFlight>>) {
+    loop {
+        match read_response(&mut stream) {
+            Ok((token, response)) => {
+                // Nobody may be waiting if the query was abandoned
+                if let Some(sender) = in_flight.lock().unwrap().senders.remove(&token) {
+                    let _ = sender.send(response);
+                }
+            },
+            Err(e) => {
+                let message = match e {
+                    SendError::IoError(message) => message,
+                    other => format!("{:?}", other)
+                };
+                let mut in_flight = in_flight.lock().unwrap();
+                for (_, sender) in in_flight.senders.drain() {
+                    let _ = sender.send(Err(SendError::IoError(message.clone())));
+                }
+                in_flight.error = Some(message);
+                return;
+            }
+        }
+    }
+}
+
 pub struct Connection {
-    state: ConnectionState,
-    query_count: u64,
+    state: RwLock<ConnectionState>,
+    query_count: AtomicUsize,
     host: String,
     port: u16,
     default_db: Option<String>,
-    auth_key: String,
+    user: String,
+    password: String,
+    tls: Option<TlsOptions>,
+    reconnect_policy: Option<ReconnectPolicy>,
     timeout_secs: u32,
 }
 
 impl Connection {
+    /// Connects as the admin user, with the auth key as the password (as servers since 2.3 treat
+    /// legacy auth keys).
     pub fn new(host: &str, port: u16, default_db: Option<&str>, auth_key: Option<&str>, timeout_secs: u32) -> Self {
+        Connection::new_with_user(host, port, default_db, "admin", auth_key.unwrap_or(""), timeout_secs)
+    }
+
+    pub fn new_with_user(host: &str, port: u16, default_db: Option<&str>, user: &str, password: &str, timeout_secs: u32) -> Self {
         Connection {
-            state: ConnectionState::Closed,
-            query_count: 0,
+            state: RwLock::new(ConnectionState::Closed),
+            query_count: AtomicUsize::new(0),
             host: host.to_string(),
             port: port,
             default_db: default_db.map(|x| x.to_string()),
-            auth_key: auth_key.unwrap_or("").to_string(),
+            user: user.to_string(),
+            password: password.to_string(),
+            tls: None,
+            reconnect_policy: None,
             timeout_secs: timeout_secs
         }
     }
 
+    /// Reconnect automatically when the socket breaks. Without a policy, every query fails
+    /// until `reconnect` is called.
+    ///
+    /// Queries which never reached the server are sent again after reconnecting, as are queries
+    /// run with `ReQL::run_read`. Other queries fail with `SendError::IndeterminateWriteError`
+    /// if the connection broke while they were in flight.
+    pub fn set_reconnect_policy(&mut self, policy: Option<ReconnectPolicy>) {
+        self.reconnect_policy = policy;
+    }
+
+    /// Encrypt the connection with TLS, or stop encrypting it. Takes effect on the next call to
+    /// `connect`.
+    pub fn use_tls(&mut self, tls: Option<TlsOptions>) {
+        self.tls = tls;
+    }
+
     pub fn connect(&mut self) -> Result<(), ConnectionError> {
-        match self.state {
-            Open(_) => Err(ConnectionError::InvalidOperationError(
+        if self.is_open() {
+            return Err(ConnectionError::InvalidOperationError(
                 "Connection must be closed before calling connect.".into()
-            )),
-            Closed => match TcpStream::connect((&*self.host, self.port)) {
-                Ok(stream) => {
-                    self.state = Open(stream);
-                    self.handshake()
-                },
-                Err(e) => {
-                    Err(ConnectionError::ConnectionError(e.description().into()))
+            ));
+        }
+        let transport = try!(self.open_transport());
+        *self.state.write().unwrap() = Open(Arc::new(transport));
+        Ok(())
+    }
+
+    fn open_transport(&self) -> Result<Transport, ConnectionError> {
+        let mut stream = try!(self.open_socket());
+        // Writes keep the connection's timeout, but reads only use it for the handshake.
+        // Afterwards each query waits for its own response with its own timeout.
+        try!(stream.set_read_timeout(self.timeout()));
+        try!(stream.set_write_timeout(self.timeout()));
+
+        match self.tls {
+            Some(ref options) => {
+                let mut session = try!(tls::client_session(&self.host, options));
+                {
+                    let mut tls_stream = TlsStream::new(&mut session, &mut stream);
+                    try!(self.handshake(&mut tls_stream));
                 }
+                try!(stream.set_read_timeout(None));
+                Transport::tls(stream, session)
+            },
+            None => {
+                try!(self.handshake(&mut stream));
+                try!(stream.set_read_timeout(None));
+                Transport::plain(stream)
+            }
+        }
+    }
+
+    // Replace a broken transport, following the reconnect policy. `attempts` counts the attempts
+    // already made for the same query, which share the policy's budget.
+    fn recover(&self, broken: &Arc<Transport>, policy: &ReconnectPolicy, attempts: &mut u32) -> Result<(), SendError> {
+        let mut last_error = ConnectionError::InvalidOperationError(
+            "The reconnect policy doesn't allow any more attempts".into());
+
+        while *attempts < policy.max_attempts {
+            // Another thread may have already reconnected, or the connection was closed
+            match *self.state.read().unwrap() {
+                Open(ref current) if !Arc::ptr_eq(current, broken) => return Ok(()),
+                Closed => return Err(SendError::ClosedConnectionError),
+                Open(..) => {}
+            }
+
+            if *attempts > 0 {
+                thread::sleep(policy.backoff(*attempts - 1));
+            }
+            *attempts += 1;
+
+            match self.open_transport() {
+                Ok(transport) => {
+                    let mut state = self.state.write().unwrap();
+                    if let Open(ref current) = *state {
+                        if !Arc::ptr_eq(current, broken) {
+                            // Somebody else got there first
+                            return Ok(());
+                        }
+                    }
+                    *state = Open(Arc::new(transport));
+                    return Ok(());
+                },
+                Err(e) => last_error = e
             }
         }
+        Err(SendError::ReconnectError(last_error))
+    }
+
+    fn open_socket(&self) -> Result<TcpStream, ConnectionError> {
+        let addrs = try!((&*self.host, self.port).to_socket_addrs().map_err(|e| {
+            ConnectionError::ConnectionError(e.description().into())
+        }));
+
+        let mut last_error = ConnectionError::ConnectionError(format!("Could not resolve {}", self.host));
+        for addr in addrs {
+            let result = match self.timeout() {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr)
+            };
+            match result {
+                Ok(stream) => return Ok(stream),
+                Err(ref e) if is_timeout(e) => {
+                    last_error = ConnectionError::TimeoutError(format!("Timed out connecting to {}", addr))
+                },
+                Err(e) => last_error = ConnectionError::ConnectionError(e.description().into())
+            }
+        }
+        Err(last_error)
     }
 
     pub fn close(&mut self) {
-        self.state = Closed
+        *self.state.write().unwrap() = Closed
     }
 
     pub fn reconnect(&mut self) -> Result<(), ConnectionError> {
@@ -146,76 +578,132 @@ impl Connection {
         self.default_db = default_db.map(|x| x.to_string());
     }
 
-    fn handshake(&mut self) -> Result<(), ConnectionError> {
-        let v4 = 0x400c2d20u32;
-        let json = 0x7e6970c7u32;
-
-        match self.state {
-            Open(ref mut stream) => {
-                try!(stream.write_u32::<LittleEndian>(v4));
-                try!(stream.write_u32::<LittleEndian>(self.auth_key.len() as u32));
-                for byte in self.auth_key.bytes() {
-                    try!(stream.write_u8(byte));
-                }
-                try!(stream.write_u32::<LittleEndian>(json));
-                let res = try!(read_string(stream));
-                if res != "SUCCESS" {
-                  Err(ConnectionError::RethinkError(res))
-                } else {
-                  Ok(())
-                }
-            },
-            Closed => Err(ConnectionError::InvalidOperationError(
-                "Tried to handshake on a closed connection!".into()))
+    fn handshake<S: Read + Write>(&self, stream: &mut S) -> Result<(), ConnectionError> {
+        let mut handshake = Handshake::new(&self.user, &self.password);
+        try!(stream.write_all(&handshake.start()));
+        loop {
+            let message = try!(read_string(stream));
+            match try!(handshake.receive(&message)) {
+                HandshakeStep::Send(bytes) => try!(stream.write_all(&bytes)),
+                HandshakeStep::Wait => {},
+                HandshakeStep::Done => return Ok(())
+            }
         }
     }
 
     // TODO(zach): Do not expose
-    pub fn send(&mut self, raw_string : &str) -> Result<json::Json, SendError> {
-        match self.state {
-            Open(ref mut stream) => {
-                self.query_count += 1;
-                try!(stream.write_u64::<LittleEndian>(self.query_count));
-
-                let bytes = raw_string.as_bytes();
-                try!(stream.write_u32::<LittleEndian>(bytes.len() as u32));
-                try!(stream.write_all(bytes));
-
-                let query_token_resp = try!(stream.read_u64::<LittleEndian>());
-                if query_token_resp != self.query_count {
-                  return Err(SendError::MismatchedQueryTokenError(
-                    format!("Query token ({}) does not match {}",
-                      query_token_resp, self.query_count)
-                    )
-                  );
-                }
+    pub fn send(&self, raw_string : &str) -> Result<json::Json, SendError> {
+        let token = self.next_token();
+        self.send_with_token(token, raw_string, self.timeout())
+    }
 
-                let resp_len = stream.read_u32::<LittleEndian>().unwrap();
-                let mut resp_bytes = Read::by_ref(stream).take(resp_len as u64);
-                json::Json::from_reader(&mut resp_bytes).map_err(|parser_error| {
-                    SendError::ResponseParseError(parser_error)})
-            },
+    pub(crate) fn next_token(&self) -> u64 {
+        (self.query_count.fetch_add(1, Ordering::SeqCst) + 1) as u64
+    }
+
+    // Sends a query under an explicit token. CONTINUE and STOP queries must reuse the token of
+    // the START query they refer to. Any number of threads may be waiting on different tokens
+    // at once.
+    pub(crate) fn send_with_token(&self, token: u64, raw_string : &str, timeout: Option<Duration>) -> Result<json::Json, SendError> {
+        try!(self.transport()).send(token, raw_string, timeout)
+    }
+
+    // Sends a query under an explicit token without waiting for the response, which is dropped
+    // when it arrives
+    pub(crate) fn send_noreply_with_token(&self, token: u64, raw_string: &str) -> Result<(), SendError> {
+        try!(self.transport()).send_noreply(token, raw_string)
+    }
+
+    // Sends a START query under a new token, recovering from a broken socket according to the
+    // reconnect policy. Returns the token the query was finally sent under.
+    pub(crate) fn start_query(&self, raw_string: &str, timeout: Option<Duration>, safe_read: bool) -> Result<(u64, json::Json), SendError> {
+        let mut attempts = 0;
+        loop {
+            let transport = try!(self.transport());
+            let token = self.next_token();
+            let error = match transport.send(token, raw_string, timeout) {
+                Ok(json) => return Ok((token, json)),
+                Err(e) => e
+            };
+
+            let policy = match self.reconnect_policy {
+                Some(ref policy) if transport.is_broken() && attempts < policy.max_attempts => policy,
+                _ => return Err(error)
+            };
+
+            let sent = match error {
+                SendError::ClosedConnectionError => false,
+                _ => true
+            };
+            try!(self.recover(&transport, policy, &mut attempts));
+            if sent && !safe_read {
+                return Err(SendError::IndeterminateWriteError(format!(
+                    "The connection failed while the query was in flight ({:?}), so it may or may not have been applied",
+                    error)));
+            }
+        }
+    }
+
+    fn transport(&self) -> Result<Arc<Transport>, SendError> {
+        match *self.state.read().unwrap() {
+            Open(ref transport) => Ok(transport.clone()),
             Closed => Err(SendError::ClosedConnectionError)
         }
     }
 
     // TODO(zach): Do not expose
     pub fn serialize_params(&self) -> String {
-        // TODO(zach): currently only default database affects this
-        match self.default_db {
-            Some(ref db_name) => format!(r##"{{"db":[14,["{}"]]}}"##, db_name),
-            _ => "{}".to_string()
+        serialize_params(&self.default_db, &[])
+    }
+
+    pub(crate) fn serialize_params_with(&self, optargs: &[(&str, String)]) -> String {
+        serialize_params(&self.default_db, optargs)
+    }
+
+    // Sends a query the server won't answer, such as one run with `noreply`
+    pub(crate) fn send_noreply(&self, raw_string: &str) -> Result<(), SendError> {
+        let token = self.next_token();
+        try!(self.transport()).send_noreply(token, raw_string)
+    }
+
+    /// Waits until the server has finished processing every `noreply` query previously sent on
+    /// this connection.
+    pub fn noreply_wait(&self) -> Result<(), RunQueryError> {
+        let json = try!(self.send(&format!("[{}]", Query_QueryType::NOREPLY_WAIT as u32)));
+        let response = try!(try!(RethinkResponse::from_json(json)).into_result());
+        match response.response_type {
+            Response_ResponseType::WAIT_COMPLETE => Ok(()),
+            other => Err(RunQueryError::ParseError(format!(
+                "Expected WAIT_COMPLETE in response to NOREPLY_WAIT, got {:?}", other)))
         }
     }
 
     pub fn is_open(&self) -> bool {
-        match self.state {
+        match *self.state.read().unwrap() {
             Open(..) => true,
             Closed => false
         }
     }
 
+    /// True if the connection is open and its socket hasn't failed. An open connection whose
+    /// socket has failed only recovers if it has a reconnect policy.
+    pub fn is_connected(&self) -> bool {
+        match *self.state.read().unwrap() {
+            Open(ref transport) => !transport.is_broken(),
+            Closed => false
+        }
+    }
+
     pub fn default_db(&self) -> &Option<String> {
         return &self.default_db
     }
+
+    /// How long to wait to connect, for each step of the handshake, and for each response. A
+    /// `timeout_secs` of 0 waits forever.
+    pub fn timeout(&self) -> Option<Duration> {
+        match self.timeout_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs as u64))
+        }
+    }
 }
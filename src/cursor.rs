@@ -0,0 +1,232 @@
+use std::collections::VecDeque;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::Duration;
+
+use datum::{Datum, DecodeOptions};
+use connection::Connection;
+use query::{ReQL, RunQueryError};
+use serde_datum::{DatumError, from_datum};
+use response::RethinkResponse;
+use profile::Profile;
+
+extern crate serde;
+use self::serde::de::DeserializeOwned;
+
+use ql2::{Query_QueryType, Response_ResponseType, Response_ResponseNote};
+
+// A cursor either borrows the connection it was run on, or shares ownership of it
+enum ConnectionRef<'a> {
+    Borrowed(&'a Connection),
+    Shared(Arc<Connection>),
+}
+
+impl<'a> Deref for ConnectionRef<'a> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        match *self {
+            ConnectionRef::Borrowed(connection) => connection,
+            ConnectionRef::Shared(ref connection) => &**connection
+        }
+    }
+}
+
+/// The results of a query, fetched from the server one batch at a time.
+///
+/// While the server reports `SUCCESS_PARTIAL`, exhausting the current batch sends a `CONTINUE`
+/// query for the next one. A cursor which is closed or dropped before the server has finished
+/// sends `STOP` so the server can release the query; only `close` waits for the server to
+/// acknowledge it.
+///
+/// Each result is decoded into `T`, which is `Datum` unless the query was run with
+/// `ReQL::run_cursor`. Errors the server reports for any batch are rendered against the query.
+pub struct Cursor<'a, T = Datum> {
+    connection: ConnectionRef<'a>,
+    query: ReQL,
+    server: Option<String>,
+    token: u64,
+    timeout: Option<Duration>,
+    decode: DecodeOptions,
+    response_type: Response_ResponseType,
+    profile: Option<Profile>,
+    notes: Vec<Response_ResponseNote>,
+    batch: VecDeque<Datum>,
+    decode_row: fn(Datum) -> Result<T, DatumError>,
+    complete: bool,
+}
+
+impl<'a> Cursor<'a> {
+    // A cursor with no results, for a query the server won't answer
+    pub(crate) fn finished(connection: &'a Connection, query: &ReQL) -> Cursor<'a> {
+        Cursor {
+            connection: ConnectionRef::Borrowed(connection),
+            query: query.clone(),
+            server: None,
+            token: 0,
+            timeout: None,
+            decode: DecodeOptions::default(),
+            response_type: Response_ResponseType::SUCCESS_SEQUENCE,
+            profile: None,
+            notes: Vec::new(),
+            batch: VecDeque::new(),
+            decode_row: Ok,
+            complete: true,
+        }
+    }
+
+    pub(crate) fn start(connection: &'a Connection, query: &ReQL, raw_query: &str, timeout: Option<Duration>, safe_read: bool, decode: DecodeOptions) -> Result<Cursor<'a>, RunQueryError> {
+        Cursor::start_on(ConnectionRef::Borrowed(connection), query, raw_query, timeout, safe_read, decode, Ok)
+    }
+}
+
+impl<'a, T: DeserializeOwned> Cursor<'a, T> {
+    pub(crate) fn start_as(connection: &'a Connection, query: &ReQL, raw_query: &str, timeout: Option<Duration>, safe_read: bool, decode: DecodeOptions) -> Result<Cursor<'a, T>, RunQueryError> {
+        Cursor::start_on(ConnectionRef::Borrowed(connection), query, raw_query, timeout, safe_read, decode, from_datum::<T>)
+    }
+}
+
+impl<'a, T> Cursor<'a, T> {
+    fn start_on(connection: ConnectionRef<'a>, query: &ReQL, raw_query: &str, timeout: Option<Duration>, safe_read: bool,
+                decode: DecodeOptions, decode_row: fn(Datum) -> Result<T, DatumError>) -> Result<Cursor<'a, T>, RunQueryError> {
+        let started = connection.start_query(raw_query, timeout, safe_read).map_err(RunQueryError::from).and_then(|(token, json)| {
+            let response = try!(try!(RethinkResponse::from_json_with(json, &decode)).into_result());
+            Ok((token, response))
+        });
+        let (token, response) = try!(started.map_err(|e| query.attach_to(e)));
+
+        let mut cursor = Cursor {
+            connection: connection,
+            query: query.clone(),
+            server: None,
+            token: token,
+            timeout: timeout,
+            decode: decode,
+            response_type: response.response_type,
+            profile: None,
+            notes: Vec::new(),
+            batch: VecDeque::new(),
+            decode_row: decode_row,
+            complete: false,
+        };
+        cursor.accept(response);
+        Ok(cursor)
+    }
+
+    /// The name of the server which ran the query, for queries run on a `Cluster`.
+    pub fn server(&self) -> Option<&str> {
+        self.server.as_ref().map(|s| &s[..])
+    }
+
+    /// The type of the most recent response received for this query.
+    pub fn response_type(&self) -> Response_ResponseType {
+        self.response_type
+    }
+
+    /// Where the server spent its time, for queries run with `profile` set in their `RunOptions`.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// The notes the server attached to the query's responses.
+    pub fn notes(&self) -> &[Response_ResponseNote] {
+        &self.notes
+    }
+
+    /// True for a changefeed, which never completes on its own, rather than a finite sequence.
+    pub fn is_feed(&self) -> bool {
+        self.feed_kind().is_some()
+    }
+
+    /// The kind of changefeed, one of `SEQUENCE_FEED`, `ATOM_FEED`, `ORDER_BY_LIMIT_FEED` or
+    /// `UNIONED_FEED`, or `None` if the query isn't a changefeed.
+    pub fn feed_kind(&self) -> Option<Response_ResponseNote> {
+        self.notes.iter().cloned().find(|&note| note != Response_ResponseNote::INCLUDES_STATES)
+    }
+
+    /// True if the changefeed was run with `include_states`, so it sends state documents as well
+    /// as changes.
+    pub fn includes_states(&self) -> bool {
+        self.notes.contains(&Response_ResponseNote::INCLUDES_STATES)
+    }
+
+    /// True once the server has sent the final batch (or the cursor was closed).
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Stops the query on the server. Any results which have already been received can still be
+    /// read from the cursor.
+    pub fn close(&mut self) -> Result<(), RunQueryError> {
+        if self.complete {
+            return Ok(());
+        }
+        self.complete = true;
+
+        let json = try!(self.connection.send_with_token(
+            self.token, &format!("[{}]", Query_QueryType::STOP as u32), self.timeout));
+        try!(RethinkResponse::from_json(json));
+        Ok(())
+    }
+
+    fn fetch_more(&mut self) -> Result<(), RunQueryError> {
+        let json = try!(self.connection.send_with_token(
+            self.token, &format!("[{}]", Query_QueryType::CONTINUE as u32), self.timeout));
+        let response = try!(try!(RethinkResponse::from_json_with(json, &self.decode)).into_result());
+        self.accept(response);
+        Ok(())
+    }
+
+    fn accept(&mut self, response: RethinkResponse) {
+        self.response_type = response.response_type;
+        // Only the first response of a query carries its profile
+        if response.profile.is_some() {
+            self.profile = response.profile;
+        }
+        if !response.notes.is_empty() {
+            self.notes = response.notes;
+        }
+        self.complete = response.response_type != Response_ResponseType::SUCCESS_PARTIAL;
+        self.batch.extend(response.result.into_iter());
+    }
+}
+
+impl Cursor<'static> {
+    // Start a query on a connection the cursor keeps alive itself
+    pub(crate) fn start_shared(connection: Arc<Connection>, server: String, query: &ReQL, raw_query: &str, timeout: Option<Duration>, safe_read: bool, decode: DecodeOptions) -> Result<Cursor<'static>, RunQueryError> {
+        let mut cursor = try!(Cursor::start_on(ConnectionRef::Shared(connection), query, raw_query, timeout, safe_read, decode, Ok));
+        cursor.server = Some(server);
+        Ok(cursor)
+    }
+}
+
+impl<'a, T> Iterator for Cursor<'a, T> {
+    type Item = Result<T, RunQueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(datum) = self.batch.pop_front() {
+                // A row which doesn't decode doesn't stop the rest from being read
+                return Some((self.decode_row)(datum).map_err(RunQueryError::from));
+            }
+            if self.complete {
+                return None;
+            }
+            if let Err(e) = self.fetch_more() {
+                // Don't keep asking for batches from a query that is in an unknown state
+                self.complete = true;
+                return Some(Err(self.query.attach_to(e)));
+            }
+        }
+    }
+}
+
+impl<'a, T> Drop for Cursor<'a, T> {
+    fn drop(&mut self) {
+        // Nobody is left to wait for the response, or to report an error to
+        if !self.complete {
+            let _ = self.connection.send_noreply_with_token(
+                self.token, &format!("[{}]", Query_QueryType::STOP as u32));
+        }
+    }
+}
@@ -1,21 +1,178 @@
+use std::collections::BTreeMap;
 use std::collections::hash_map::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 extern crate rustc_serialize;
 use self::rustc_serialize::json;
+use self::rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+
+#[cfg(feature = "chrono")]
+extern crate chrono;
+#[cfg(feature = "chrono")]
+use self::chrono::{DateTime, FixedOffset, Offset, TimeZone};
+
+use ql2::Term_TermType;
+use serde_datum::DatumError;
+
+/// An error turning a datum into a query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SerializeError {
+    /// NaN and the infinities have no JSON representation
+    NonFiniteNumber(f64),
+    /// A document couldn't be converted into a datum
+    InvalidDocument(DatumError),
+}
+
+/// Which pseudo-types are left as the raw objects the server sent, rather than decoded into
+/// their own variants. Set from the `*_format` fields of `RunOptions`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DecodeOptions {
+    pub raw_time: bool,
+    pub raw_binary: bool,
+    pub raw_groups: bool,
+}
+
+/// A position on the Earth, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LonLat {
+    pub longitude: f64,
+    pub latitude: f64,
+}
+
+impl From<(f64, f64)> for LonLat {
+    fn from((longitude, latitude): (f64, f64)) -> LonLat {
+        LonLat { longitude: longitude, latitude: latitude }
+    }
+}
+
+impl From<LonLat> for (f64, f64) {
+    fn from(point: LonLat) -> (f64, f64) {
+        (point.longitude, point.latitude)
+    }
+}
+
+/// A JSON number. Integers are kept as integers, so ids and counters too large for an `f64` to
+/// hold exactly survive being read and written back. The server itself stores every number as
+/// a double.
+///
+/// Numbers compare equal when they have the same value, whichever variant holds them.
+#[derive(Debug, Clone, Copy)]
+pub enum Number {
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+}
+
+impl Number {
+    /// The number as an `i64`, or `None` if it is fractional or out of range.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Number::Int(n) => Some(n),
+            Number::UInt(n) if n <= i64::max_value() as u64 => Some(n as i64),
+            Number::Float(n) if n.fract() == 0.0 && n >= -9223372036854775808.0 && n < 9223372036854775808.0 => Some(n as i64),
+            _ => None
+        }
+    }
+
+    /// The number as a `u64`, or `None` if it is negative, fractional or out of range.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Number::Int(n) if n >= 0 => Some(n as u64),
+            Number::UInt(n) => Some(n),
+            Number::Float(n) if n.fract() == 0.0 && n >= 0.0 && n < 18446744073709551616.0 => Some(n as u64),
+            _ => None
+        }
+    }
+
+    /// The number as an `f64`. Integers beyond 2^53 are rounded.
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            Number::Int(n) => n as f64,
+            Number::UInt(n) => n as f64,
+            Number::Float(n) => n
+        }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        match *self {
+            Number::Float(n) => n.is_finite(),
+            _ => true
+        }
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Number) -> bool {
+        if let (&Number::Float(a), &Number::Float(b)) = (self, other) {
+            return a == b;
+        }
+        // Anything which isn't a whole number has no integer form, so only equals itself
+        match (self.as_i64(), other.as_i64()) {
+            (Some(a), Some(b)) => a == b,
+            _ => match (self.as_u64(), other.as_u64()) {
+                (Some(a), Some(b)) => a == b,
+                _ => false
+            }
+        }
+    }
+}
+
+// Floats are written with an exponent when they are very large or very small, rather than as
+// a long run of digits, which parsers that read integers into a u64 overflow on
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Number::Int(n) => write!(f, "{}", n),
+            Number::UInt(n) => write!(f, "{}", n),
+            Number::Float(n) if !n.is_finite() || n == 0.0 => write!(f, "{}", n),
+            Number::Float(n) if n.abs() >= 1e-4 && n.abs() < 1e16 => write!(f, "{}", n),
+            Number::Float(n) => write!(f, "{:e}", n)
+        }
+    }
+}
+
+macro_rules! number_from {
+    ($variant:ident, $repr:ty, $($t:ty),*) => {
+        $(
+            impl From<$t> for Number {
+                fn from(n: $t) -> Number {
+                    Number::$variant(n as $repr)
+                }
+            }
+        )*
+    }
+}
+
+number_from!(Int, i64, i8, i16, i32, i64, isize);
+number_from!(UInt, u64, u8, u16, u32, u64, usize);
+number_from!(Float, f64, f32, f64);
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum Datum {
     Null,
     Bool(bool),
     String(String),
-    Number(f64),
+    Number(Number),
     Array(Vec<Datum>),
     Object(HashMap<String, Datum>),
-    // Time ZonedTime |
-    // Point LonLat |
-    // Line Line |
-    // Polygon Polygon |
-    // Binary SB.ByteString
+    /// A point in time, as seconds since the Unix epoch, and the offset from UTC in seconds of
+    /// the timezone it is in
+    Time {
+        epoch_time: f64,
+        utc_offset: i32,
+    },
+    /// Binary data, such as the contents of a file
+    Binary(Vec<u8>),
+    Point(LonLat),
+    /// A line through two or more points
+    Line(Vec<LonLat>),
+    /// An outer ring of points, followed by any holes cut out of it. Rings read from the server
+    /// end with their first point.
+    Polygon(Vec<Vec<LonLat>>),
+    /// The result of a `group` query: each group, paired with its rows or their reduction, in
+    /// the order the server sent them
+    Grouped(Vec<(Datum, Datum)>),
 }
 
 impl Datum {
@@ -26,39 +183,703 @@ impl Datum {
 
     // TODO(zach): Do not expose
     pub fn from_json(json: json::Json) -> Self {
+        Datum::from_json_with(json, &DecodeOptions::default())
+    }
+
+    pub(crate) fn from_json_with(json: json::Json, options: &DecodeOptions) -> Self {
         match json {
             json::Json::Null => Datum::Null,
             json::Json::Boolean(b) => Datum::Bool(b),
             json::Json::String(s) => Datum::String(s),
-            json::Json::U64(n) => Datum::Number(n as f64),
-            json::Json::I64(n) => Datum::Number(n as f64),
-            json::Json::F64(n) => Datum::Number(n),
+            json::Json::U64(n) => Datum::Number(Number::UInt(n)),
+            json::Json::I64(n) => Datum::Number(Number::Int(n)),
+            json::Json::F64(n) => Datum::Number(Number::Float(n)),
             json::Json::Array(json_array) => {
                 Datum::Array(json_array.into_iter().map(|json| {
-                    Datum::from_json(json)
+                    Datum::from_json_with(json, options)
                 }).collect())
             },
             json::Json::Object(json_object) => {
-                Datum::Object(json_object.into_iter().map(|(k, json)| {
-                    (k, Datum::from_json(json))
-                }).collect())
+                from_object(json_object.into_iter().map(|(k, json)| {
+                    (k, Datum::from_json_with(json, options))
+                }).collect(), options)
             }
         }
     }
 
+    /// The time `time`, in UTC.
+    pub fn from_system_time(time: SystemTime) -> Datum {
+        let epoch_time = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => seconds(since),
+            Err(before) => -seconds(before.duration())
+        };
+        Datum::Time { epoch_time: epoch_time, utc_offset: 0 }
+    }
+
+    /// The point in time a `Datum::Time` refers to, or `None` for any other datum.
+    pub fn to_system_time(&self) -> Option<SystemTime> {
+        match *self {
+            Datum::Time { epoch_time, .. } if epoch_time.is_finite() => {
+                let seconds = epoch_time.abs().trunc();
+                // Times too far from the epoch for a SystemTime
+                if seconds >= u64::max_value() as f64 {
+                    return None;
+                }
+                let since = Duration::new(seconds as u64, (epoch_time.abs().fract() * 1e9) as u32);
+                if epoch_time >= 0.0 { UNIX_EPOCH.checked_add(since) } else { UNIX_EPOCH.checked_sub(since) }
+            },
+            _ => None
+        }
+    }
+
+    // The object the server represents a pseudo-type as
+    pub(crate) fn pseudo_type(&self) -> Option<Datum> {
+        let mut object = HashMap::new();
+        match *self {
+            Datum::Time { epoch_time, utc_offset } => {
+                object.insert("$reql_type$".to_string(), Datum::String("TIME".to_string()));
+                object.insert("epoch_time".to_string(), Datum::Number(Number::Float(epoch_time)));
+                object.insert("timezone".to_string(), Datum::String(format_timezone(utc_offset)));
+            },
+            Datum::Binary(ref data) => {
+                object.insert("$reql_type$".to_string(), Datum::String("BINARY".to_string()));
+                object.insert("data".to_string(), Datum::String(data.to_base64(STANDARD)));
+            },
+            Datum::Grouped(ref groups) => {
+                object.insert("$reql_type$".to_string(), Datum::String("GROUPED_DATA".to_string()));
+                object.insert("data".to_string(), Datum::Array(groups.iter().map(|&(ref group, ref reduction)| {
+                    Datum::Array(vec![group.clone(), reduction.clone()])
+                }).collect()));
+            },
+            Datum::Point(point) => geometry(&mut object, "Point", coordinates(point)),
+            Datum::Line(ref points) => {
+                geometry(&mut object, "LineString", Datum::Array(points.iter().cloned().map(coordinates).collect()))
+            },
+            Datum::Polygon(ref rings) => {
+                geometry(&mut object, "Polygon", Datum::Array(rings.iter().map(|ring| {
+                    Datum::Array(ring.iter().cloned().map(coordinates).collect())
+                }).collect()))
+            },
+            _ => return None
+        }
+        Some(Datum::Object(object))
+    }
+
+    /// The datum as plain JSON.
+    pub fn to_json(&self) -> json::Json {
+        match *self {
+            Datum::Null => json::Json::Null,
+            Datum::Bool(b) => json::Json::Boolean(b),
+            Datum::String(ref s) => json::Json::String(s.clone()),
+            Datum::Number(Number::Int(n)) => json::Json::I64(n),
+            Datum::Number(Number::UInt(n)) => json::Json::U64(n),
+            Datum::Number(Number::Float(n)) => json::Json::F64(n),
+            Datum::Array(ref a) => json::Json::Array(a.iter().map(Datum::to_json).collect()),
+            Datum::Object(ref m) => json::Json::Object(m.iter().map(|(k, datum)| {
+                (k.clone(), datum.to_json())
+            }).collect()),
+            _ => self.pseudo_type().unwrap().to_json()
+        }
+    }
+
+    // Arrays are sent as MAKE_ARRAY terms, since a bare JSON array in a query is a term itself
     // TODO(zach): Do not expose
-    pub fn serialize(&self) -> String {
-        match self {
+    pub fn serialize(&self) -> Result<String, SerializeError> {
+        Ok(match self {
             &Datum::Null => "null".to_string(),
             &Datum::Bool(b) => (if b { "true" } else { "false" }).to_string(),
-            &Datum::String(ref s) => format!("\"{}\"", s),
-            &Datum::Number(n) => n.to_string(),
+            &Datum::String(ref s) => quote(s),
+            &Datum::Number(n) => {
+                if !n.is_finite() {
+                    return Err(SerializeError::NonFiniteNumber(n.as_f64()));
+                }
+                n.to_string()
+            },
+            &Datum::Array(ref a) => {
+                let elements = try!(a.iter().map(Datum::serialize).collect::<Result<Vec<_>, _>>());
+                format!("[{},[{}]]", Term_TermType::MAKE_ARRAY as u32, elements.join(","))
+            },
             &Datum::Object(ref m) => {
-                format!("{{{}}}", m.iter().map(|(k, datum)| {
-                    format!("\"{}\":{}", k, datum.serialize())
-                }).collect::<Vec<_>>().connect(","))
+                let fields = try!(m.iter().map(|(k, datum)| {
+                    datum.serialize().map(|value| format!("{}:{}", quote(k), value))
+                }).collect::<Result<Vec<_>, _>>());
+                format!("{{{}}}", fields.join(","))
+            },
+            &Datum::Point(point) => try!(serialize_point(point)),
+            &Datum::Line(ref points) => try!(serialize_term(Term_TermType::LINE, points.iter().cloned().map(serialize_point))),
+            &Datum::Polygon(ref rings) => {
+                let mut rings = rings.iter().map(|ring| {
+                    // POLYGON closes the ring itself
+                    let points = if ring.len() > 1 && ring.first() == ring.last() { &ring[..ring.len() - 1] } else { &ring[..] };
+                    serialize_term(Term_TermType::POLYGON, points.iter().cloned().map(serialize_point))
+                });
+                let outer = match rings.next() {
+                    Some(outer) => try!(outer),
+                    None => try!(serialize_term(Term_TermType::POLYGON, Vec::new().into_iter()))
+                };
+                // Each hole is cut out of the polygon in turn
+                try!(rings.fold(Ok(outer), |polygon, hole| {
+                    serialize_term(Term_TermType::POLYGON_SUB, vec![polygon, hole].into_iter())
+                }))
+            },
+            _ => return self.pseudo_type().unwrap().serialize()
+        })
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl Datum {
+    /// The time `time`, keeping its offset from UTC.
+    pub fn from_chrono<Tz: TimeZone>(time: &DateTime<Tz>) -> Datum {
+        Datum::Time {
+            epoch_time: time.timestamp() as f64 + time.timestamp_subsec_nanos() as f64 / 1e9,
+            utc_offset: time.offset().fix().local_minus_utc(),
+        }
+    }
+
+    /// A `Datum::Time` as a chrono time in its own timezone, or `None` for any other datum.
+    pub fn to_chrono(&self) -> Option<DateTime<FixedOffset>> {
+        match *self {
+            Datum::Time { epoch_time, utc_offset } if epoch_time.is_finite() => {
+                let seconds = epoch_time.floor();
+                let nanos = ((epoch_time - seconds) * 1e9).min(999999999.0) as u32;
+                FixedOffset::east_opt(utc_offset).and_then(|offset| offset.timestamp_opt(seconds as i64, nanos).single())
             },
-            x => format!("unimplemented: {:?}", x)
+            _ => None
+        }
+    }
+}
+
+impl From<bool> for Datum {
+    fn from(b: bool) -> Datum {
+        Datum::Bool(b)
+    }
+}
+
+impl From<Number> for Datum {
+    fn from(n: Number) -> Datum {
+        Datum::Number(n)
+    }
+}
+
+macro_rules! datum_from_number {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for Datum {
+                fn from(n: $t) -> Datum {
+                    Datum::Number(Number::from(n))
+                }
+            }
+        )*
+    }
+}
+
+datum_from_number!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize, f32, f64);
+
+impl<'a> From<&'a str> for Datum {
+    fn from(s: &'a str) -> Datum {
+        Datum::String(s.to_string())
+    }
+}
+
+impl From<String> for Datum {
+    fn from(s: String) -> Datum {
+        Datum::String(s)
+    }
+}
+
+// Use `Datum::Binary` for bytes, which would otherwise become an array of numbers
+impl<T: Into<Datum>> From<Vec<T>> for Datum {
+    fn from(elements: Vec<T>) -> Datum {
+        Datum::Array(elements.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Datum>> From<Option<T>> for Datum {
+    fn from(value: Option<T>) -> Datum {
+        match value {
+            Some(value) => value.into(),
+            None => Datum::Null
+        }
+    }
+}
+
+impl<K: Into<String>, V: Into<Datum>, S> From<HashMap<K, V, S>> for Datum {
+    fn from(object: HashMap<K, V, S>) -> Datum {
+        Datum::Object(object.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+
+impl<K: Into<String>, V: Into<Datum>> From<BTreeMap<K, V>> for Datum {
+    fn from(object: BTreeMap<K, V>) -> Datum {
+        Datum::Object(object.into_iter().map(|(k, v)| (k.into(), v.into())).collect())
+    }
+}
+
+// Tuples become arrays, as they do through serde
+macro_rules! datum_from_tuple {
+    ($($t:ident $value:ident),+) => {
+        impl<$($t: Into<Datum>),+> From<($($t,)+)> for Datum {
+            fn from(($($value,)+): ($($t,)+)) -> Datum {
+                Datum::Array(vec![$($value.into()),+])
+            }
         }
     }
 }
+
+datum_from_tuple!(A a);
+datum_from_tuple!(A a, B b);
+datum_from_tuple!(A a, B b, C c);
+datum_from_tuple!(A a, B b, C c, D d);
+datum_from_tuple!(A a, B b, C c, D d, E e);
+datum_from_tuple!(A a, B b, C c, D d, E e, F f);
+datum_from_tuple!(A a, B b, C c, D d, E e, F f, G g);
+datum_from_tuple!(A a, B b, C c, D d, E e, F f, G g, H h);
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+// Decode the pseudo-type an object stands for, unless it should be left raw
+pub(crate) fn from_object(object: HashMap<String, Datum>, options: &DecodeOptions) -> Datum {
+    let decoded = match object.get("$reql_type$") {
+        Some(&Datum::String(ref reql_type)) => match &reql_type[..] {
+            "TIME" if !options.raw_time => parse_time(&object),
+            "BINARY" if !options.raw_binary => parse_binary(&object),
+            "GEOMETRY" => parse_geometry(&object),
+            "GROUPED_DATA" if !options.raw_groups => parse_grouped(&object),
+            _ => None
+        },
+        _ => None
+    };
+    match decoded {
+        Some(datum) => datum,
+        None => Datum::Object(object)
+    }
+}
+
+fn parse_time(object: &HashMap<String, Datum>) -> Option<Datum> {
+    match (object.get("epoch_time"), object.get("timezone")) {
+        (Some(&Datum::Number(epoch_time)), Some(&Datum::String(ref timezone))) => {
+            parse_timezone(timezone).map(|utc_offset| Datum::Time { epoch_time: epoch_time.as_f64(), utc_offset: utc_offset })
+        },
+        _ => None
+    }
+}
+
+fn geometry(object: &mut HashMap<String, Datum>, geometry_type: &str, coordinates: Datum) {
+    object.insert("$reql_type$".to_string(), Datum::String("GEOMETRY".to_string()));
+    object.insert("type".to_string(), Datum::String(geometry_type.to_string()));
+    object.insert("coordinates".to_string(), coordinates);
+}
+
+fn coordinates(point: LonLat) -> Datum {
+    Datum::Array(vec![Datum::Number(Number::Float(point.longitude)), Datum::Number(Number::Float(point.latitude))])
+}
+
+fn serialize_point(point: LonLat) -> Result<String, SerializeError> {
+    serialize_term(Term_TermType::POINT, vec![Datum::Number(Number::Float(point.longitude)).serialize(),
+                                              Datum::Number(Number::Float(point.latitude)).serialize()].into_iter())
+}
+
+fn serialize_term<I>(command: Term_TermType, arguments: I) -> Result<String, SerializeError>
+    where I: Iterator<Item = Result<String, SerializeError>> {
+    let arguments = try!(arguments.collect::<Result<Vec<_>, _>>());
+    Ok(format!("[{},[{}]]", command as u32, arguments.join(",")))
+}
+
+fn parse_geometry(object: &HashMap<String, Datum>) -> Option<Datum> {
+    let coordinates = match object.get("coordinates") {
+        Some(coordinates) => coordinates,
+        None => return None
+    };
+    match object.get("type") {
+        Some(&Datum::String(ref geometry_type)) => match &geometry_type[..] {
+            "Point" => parse_lon_lat(coordinates).map(Datum::Point),
+            "LineString" => parse_lon_lats(coordinates).map(Datum::Line),
+            "Polygon" => match *coordinates {
+                Datum::Array(ref rings) => rings.iter().map(parse_lon_lats).collect::<Option<Vec<_>>>().map(Datum::Polygon),
+                _ => None
+            },
+            _ => None
+        },
+        _ => None
+    }
+}
+
+fn parse_lon_lat(coordinates: &Datum) -> Option<LonLat> {
+    match *coordinates {
+        Datum::Array(ref pair) if pair.len() == 2 => match (&pair[0], &pair[1]) {
+            (&Datum::Number(longitude), &Datum::Number(latitude)) => {
+                Some(LonLat { longitude: longitude.as_f64(), latitude: latitude.as_f64() })
+            },
+            _ => None
+        },
+        _ => None
+    }
+}
+
+fn parse_lon_lats(coordinates: &Datum) -> Option<Vec<LonLat>> {
+    match *coordinates {
+        Datum::Array(ref points) => points.iter().map(parse_lon_lat).collect(),
+        _ => None
+    }
+}
+
+fn parse_grouped(object: &HashMap<String, Datum>) -> Option<Datum> {
+    match object.get("data") {
+        Some(&Datum::Array(ref data)) => data.iter().map(|pair| match *pair {
+            Datum::Array(ref pair) if pair.len() == 2 => Some((pair[0].clone(), pair[1].clone())),
+            _ => None
+        }).collect::<Option<Vec<_>>>().map(Datum::Grouped),
+        _ => None
+    }
+}
+
+fn parse_binary(object: &HashMap<String, Datum>) -> Option<Datum> {
+    match object.get("data") {
+        Some(&Datum::String(ref data)) => data.from_base64().ok().map(Datum::Binary),
+        _ => None
+    }
+}
+
+// Timezones are written as "[+-]HH:MM"
+fn parse_timezone(timezone: &str) -> Option<i32> {
+    let bytes = timezone.as_bytes();
+    if bytes.len() != 6 || bytes[3] != b':' {
+        return None;
+    }
+    let sign = match bytes[0] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None
+    };
+    let hours = match timezone[1..3].parse::<i32>() { Ok(hours) => hours, Err(_) => return None };
+    let minutes = match timezone[4..6].parse::<i32>() { Ok(minutes) => minutes, Err(_) => return None };
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+fn format_timezone(utc_offset: i32) -> String {
+    let sign = if utc_offset < 0 { '-' } else { '+' };
+    let minutes = utc_offset.abs() / 60;
+    format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+}
+
+// A string as a JSON string literal, with quotes, backslashes and control characters escaped
+pub(crate) fn quote(s: &str) -> String {
+    json::Json::String(s.to_string()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern crate rand;
+    use self::rand::Rng;
+
+    // Strings full of the characters which need escaping
+    fn random_string<R: Rng>(rng: &mut R) -> String {
+        let special = ['"', '\\', '/', '\n', '\r', '\t', '\u{0}', '\u{1f}', '\u{7f}', '\u{2028}', 'é', '\u{1f600}', ']', '}', ','];
+        let len = rng.gen_range(0, 12);
+        (0..len).map(|_| {
+            if rng.gen() {
+                special[rng.gen_range(0, special.len())]
+            } else {
+                rng.gen_range(b' ', b'~' + 1) as char
+            }
+        }).collect()
+    }
+
+    // Undo the MAKE_ARRAY terms arrays are serialized as
+    fn unwrap_arrays(json: json::Json) -> json::Json {
+        match json {
+            json::Json::Array(mut term) => {
+                assert_eq!(term.len(), 2);
+                assert_eq!(term[0], json::Json::U64(Term_TermType::MAKE_ARRAY as u64));
+                match term.pop() {
+                    Some(json::Json::Array(elements)) => json::Json::Array(elements.into_iter().map(unwrap_arrays).collect()),
+                    other => panic!("MAKE_ARRAY takes an array of arguments, got {:?}", other)
+                }
+            },
+            json::Json::Object(o) => json::Json::Object(o.into_iter().map(|(k, v)| (k, unwrap_arrays(v))).collect()),
+            other => other
+        }
+    }
+
+    fn random_datum<R: Rng>(rng: &mut R, depth: u32) -> Datum {
+        let kinds = if depth == 0 { 6 } else { 8 };
+        match rng.gen_range(0, kinds) {
+            0 => Datum::Null,
+            1 => Datum::Bool(rng.gen()),
+            2 => Datum::String(random_string(rng)),
+            3 => Datum::Number(match rng.gen_range(0, 3) {
+                0 => Number::Int(rng.gen_range(-i64::max_value(), i64::max_value())),
+                1 => Number::UInt(rng.gen()),
+                // rustc_serialize's parser rounds long fractions, so stick to ones it reads exactly
+                _ => Number::Float(rng.gen_range(-1000000i64, 1000000) as f64 / 2.0)
+            }),
+            4 => Datum::Time {
+                epoch_time: rng.gen_range(-4000000000i64, 4000000000) as f64 / 2.0,
+                utc_offset: rng.gen_range(-24 * 60 + 1, 24 * 60) * 60,
+            },
+            5 => Datum::Binary((0..rng.gen_range(0, 16)).map(|_| rng.gen()).collect()),
+            6 => Datum::Array((0..rng.gen_range(0, 4)).map(|_| random_datum(rng, depth - 1)).collect()),
+            _ => Datum::Object((0..rng.gen_range(0, 4)).map(|_| {
+                (random_string(rng), random_datum(rng, depth - 1))
+            }).collect())
+        }
+    }
+
+    #[test]
+    fn serialize_round_trips() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let datum = random_datum(&mut rng, 3);
+            let serialized = datum.serialize().unwrap();
+            let parsed = json::Json::from_str(&serialized).unwrap_or_else(|e| {
+                panic!("{:?} serialized to invalid JSON {}: {}", datum, serialized, e)
+            });
+            assert_eq!(Datum::from_json(unwrap_arrays(parsed)), datum);
+        }
+    }
+
+    #[test]
+    fn to_json_round_trips() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let datum = Datum::Array(vec![random_datum(&mut rng, 3), random_datum(&mut rng, 2)]);
+            let parsed = json::Json::from_str(&datum.to_json().to_string()).unwrap();
+            assert_eq!(Datum::from_json(parsed), datum);
+        }
+    }
+
+    #[test]
+    fn serialize_escapes_strings() {
+        let injected = r#"x"]],[15,["secret"#;
+        assert_eq!(Datum::String(injected.to_string()).serialize().unwrap(), r#""x\"]],[15,[\"secret""#);
+        assert_eq!(Datum::String("a\nb\u{1}".to_string()).serialize().unwrap(), r#""a\nb\u0001""#);
+    }
+
+    #[test]
+    fn serialize_arrays() {
+        let datum = Datum::from_str(r#"[1, "two", [], {"three": [null]}]"#);
+        assert_eq!(datum.serialize().unwrap(), r#"[2,[1,"two",[2,[]],{"three":[2,[null]]}]]"#);
+    }
+
+    #[test]
+    fn serialize_rejects_non_finite_numbers() {
+        assert!(matches!(Datum::Number(Number::Float(::std::f64::NAN)).serialize(), Err(SerializeError::NonFiniteNumber(n)) if n.is_nan()));
+        let nested = Datum::Array(vec![Datum::Number(Number::Float(1.0)), Datum::Number(Number::Float(::std::f64::INFINITY))]);
+        assert_eq!(nested.serialize(), Err(SerializeError::NonFiniteNumber(::std::f64::INFINITY)));
+    }
+
+    #[test]
+    fn integers_round_trip_exactly() {
+        let big = Datum::from_str("[9007199254740993, 18446744073709551615, -9223372036854775807]");
+        assert_eq!(big, Datum::Array(vec![Datum::Number(Number::UInt(9007199254740993)),
+                                          Datum::Number(Number::UInt(u64::max_value())),
+                                          Datum::Number(Number::Int(-i64::max_value()))]));
+        assert_eq!(big.serialize().unwrap(), "[2,[9007199254740993,18446744073709551615,-9223372036854775807]]");
+        assert_eq!(big.to_json().to_string(), "[9007199254740993,18446744073709551615,-9223372036854775807]");
+
+        // 2^53 + 1 has no exact f64, so it isn't equal to the nearest one
+        assert!(Number::UInt(9007199254740993) != Number::Float(9007199254740992.0));
+        assert_eq!(Number::UInt(42), Number::Float(42.0));
+        assert_eq!(Number::Int(42), Number::UInt(42));
+        assert!(Number::Int(-1) != Number::UInt(u64::max_value()));
+    }
+
+    #[test]
+    fn checked_number_conversions() {
+        assert_eq!(Number::UInt(u64::max_value()).as_i64(), None);
+        assert_eq!(Number::UInt(u64::max_value()).as_u64(), Some(u64::max_value()));
+        assert_eq!(Number::Int(-1).as_u64(), None);
+        assert_eq!(Number::Float(1.5).as_i64(), None);
+        assert_eq!(Number::Float(1e20).as_i64(), None);
+        assert_eq!(Number::Float(-3.0).as_i64(), Some(-3));
+        assert_eq!(Number::Float(::std::f64::NAN).as_u64(), None);
+    }
+
+    #[test]
+    fn formats_floats() {
+        assert_eq!(Number::Float(2.0).to_string(), "2");
+        assert_eq!(Number::Float(-0.25).to_string(), "-0.25");
+        assert_eq!(Number::Float(1e21).to_string(), "1e21");
+        assert_eq!(Number::Float(1.5e-7).to_string(), "1.5e-7");
+        // Written out in full, these overflow rustc_serialize's integer parsing
+        for &n in &[1e21, -4e300] {
+            let serialized = Datum::Number(Number::Float(n)).serialize().unwrap();
+            assert!(json::Json::from_str(&serialized).unwrap().is_f64());
+        }
+    }
+
+    #[test]
+    fn decode_time() {
+        let time = Datum::from_str(r#"{"$reql_type$": "TIME", "epoch_time": 1500000000.5, "timezone": "-07:30"}"#);
+        assert_eq!(time, Datum::Time { epoch_time: 1500000000.5, utc_offset: -27000 });
+        assert_eq!(time.to_system_time(), Some(UNIX_EPOCH + Duration::new(1500000000, 500000000)));
+
+        let options = DecodeOptions { raw_time: true, ..Default::default() };
+        let raw = Datum::from_json_with(json::Json::from_str(r#"{"$reql_type$": "TIME", "epoch_time": 0, "timezone": "+00:00"}"#).unwrap(), &options);
+        assert!(matches!(raw, Datum::Object(..)));
+
+        // Times beyond what a SystemTime can hold
+        assert_eq!(Datum::Time { epoch_time: 1e20, utc_offset: 0 }.to_system_time(), None);
+        assert_eq!(Datum::Time { epoch_time: -1e20, utc_offset: 0 }.to_system_time(), None);
+
+        // Objects which only look like times are left alone
+        assert!(matches!(Datum::from_str(r#"{"$reql_type$": "TIME", "epoch_time": "soon"}"#), Datum::Object(..)));
+        for timezone in &["+24:00", "-23:60", "+99:99"] {
+            let json = format!(r#"{{"$reql_type$": "TIME", "epoch_time": 0, "timezone": "{}"}}"#, timezone);
+            assert!(matches!(Datum::from_str(&json), Datum::Object(..)));
+        }
+    }
+
+    #[test]
+    fn serialize_time() {
+        let time = Datum::Time { epoch_time: -1.5, utc_offset: 19800 };
+        let json = json::Json::from_str(&time.serialize().unwrap()).unwrap();
+        assert_eq!(json.find("timezone").unwrap().as_string(), Some("+05:30"));
+        assert_eq!(json.find("epoch_time").unwrap().as_f64(), Some(-1.5));
+        assert_eq!(Datum::from_json(json), time);
+    }
+
+    #[test]
+    fn binary() {
+        let binary = Datum::Binary(vec![0, 1, 254, 255]);
+        let json = json::Json::from_str(&binary.serialize().unwrap()).unwrap();
+        assert_eq!(json.find("$reql_type$").unwrap().as_string(), Some("BINARY"));
+        assert_eq!(json.find("data").unwrap().as_string(), Some("AAH+/w=="));
+        assert_eq!(Datum::from_json(json.clone()), binary);
+
+        let options = DecodeOptions { raw_binary: true, ..Default::default() };
+        assert!(matches!(Datum::from_json_with(json, &options), Datum::Object(..)));
+    }
+
+    #[test]
+    fn decode_geometry() {
+        let point = Datum::from_str(r#"{"$reql_type$": "GEOMETRY", "type": "Point", "coordinates": [-122.4, 37.7]}"#);
+        assert_eq!(point, Datum::Point(LonLat::from((-122.4, 37.7))));
+        assert_eq!(Datum::from_json(point.to_json()), point);
+
+        let polygon = Datum::from_str(r#"{"$reql_type$": "GEOMETRY", "type": "Polygon", "coordinates": [
+            [[0, 0], [0, 10], [10, 10], [0, 0]],
+            [[1, 1], [1, 2], [2, 2], [1, 1]]
+        ]}"#);
+        match polygon {
+            Datum::Polygon(ref rings) => {
+                assert_eq!(rings.len(), 2);
+                assert_eq!(<(f64, f64)>::from(rings[0][2]), (10.0, 10.0));
+            },
+            ref other => panic!("expected a polygon, got {:?}", other)
+        }
+        assert_eq!(Datum::from_json(polygon.to_json()), polygon);
+    }
+
+    #[test]
+    fn serialize_geometry() {
+        let point = |lon, lat| LonLat { longitude: lon, latitude: lat };
+        assert_eq!(Datum::Point(point(1.5, -2.0)).serialize().unwrap(), "[159,[1.5,-2]]");
+        assert_eq!(Datum::Line(vec![point(0.0, 0.0), point(1.0, 1.0)]).serialize().unwrap(),
+                   "[160,[[159,[0,0]],[159,[1,1]]]]");
+
+        let outer = vec![point(0.0, 0.0), point(0.0, 10.0), point(10.0, 10.0), point(0.0, 0.0)];
+        let hole = vec![point(1.0, 1.0), point(1.0, 2.0), point(2.0, 2.0)];
+        assert_eq!(Datum::Polygon(vec![outer, hole]).serialize().unwrap(), concat!(
+            "[171,[[161,[[159,[0,0]],[159,[0,10]],[159,[10,10]]]],",
+            "[161,[[159,[1,1]],[159,[1,2]],[159,[2,2]]]]]]"));
+
+        assert!(Datum::Point(point(::std::f64::NAN, 0.0)).serialize().is_err());
+    }
+
+    #[test]
+    fn decode_grouped_data() {
+        let json = json::Json::from_str(r#"{"$reql_type$": "GROUPED_DATA", "data": [["b", 2], [["a", 1], [{"id": 1}]]]}"#).unwrap();
+        let grouped = Datum::from_json(json.clone());
+        assert_eq!(grouped, Datum::Grouped(vec![
+            (Datum::String("b".to_string()), Datum::Number(Number::UInt(2))),
+            (Datum::from_str(r#"["a", 1]"#), Datum::from_str(r#"[{"id": 1}]"#)),
+        ]));
+        assert_eq!(Datum::from_json(grouped.to_json()), grouped);
+
+        let options = DecodeOptions { raw_groups: true, ..Default::default() };
+        assert!(matches!(Datum::from_json_with(json, &options), Datum::Object(..)));
+    }
+
+    #[test]
+    fn from_rust_values() {
+        assert_eq!(Datum::from(true), Datum::Bool(true));
+        assert_eq!(Datum::from(-3i8), Datum::Number(Number::Int(-3)));
+        assert_eq!(Datum::from(u64::max_value()), Datum::Number(Number::UInt(u64::max_value())));
+        assert_eq!(Datum::from(0.5f32), Datum::Number(Number::Float(0.5)));
+        assert_eq!(Datum::from("a"), Datum::String("a".to_string()));
+        assert_eq!(Datum::from(vec![Some(1), None]), Datum::from_str("[1, null]"));
+        assert_eq!(Datum::from((1, "two", (3.5,))), Datum::from_str(r#"[1, "two", [3.5]]"#));
+
+        let mut hash_map = HashMap::new();
+        hash_map.insert("a", vec!["b"]);
+        assert_eq!(Datum::from(hash_map), Datum::from_str(r#"{"a": ["b"]}"#));
+        let mut btree_map = BTreeMap::new();
+        btree_map.insert("a".to_string(), Datum::Null);
+        assert_eq!(Datum::from(btree_map), Datum::from_str(r#"{"a": null}"#));
+    }
+
+    #[test]
+    fn datum_macro() {
+        let key = "k1".to_string();
+        let tags = vec!["x"];
+        let datum = datum!{
+            "id": key,
+            "count": 2 + 1,
+            "ratio": -0.5,
+            "tags": ["a", tags.len(), [], {}],
+            "owner": { "name": "n", "tags": tags },
+            ("comp".to_string() + "uted"): null,
+        };
+        assert_eq!(datum, Datum::from_str(r#"{
+            "id": "k1",
+            "count": 3,
+            "ratio": -0.5,
+            "tags": ["a", 1, [], {}],
+            "owner": {"name": "n", "tags": ["x"]},
+            "computed": null
+        }"#));
+
+        assert_eq!(datum!(null), Datum::Null);
+        assert_eq!(datum!([1, [true, "b"], null]), Datum::from_str(r#"[1, [true, "b"], null]"#));
+        assert_eq!(datum!({}), Datum::Object(HashMap::new()));
+        assert_eq!(datum!(Some(1.5)), Datum::Number(Number::Float(1.5)));
+    }
+
+    #[test]
+    fn system_time_round_trips() {
+        for &since in &[Duration::new(1500000000, 250000000), Duration::new(0, 0)] {
+            let time = UNIX_EPOCH + since;
+            assert_eq!(Datum::from_system_time(time).to_system_time(), Some(time));
+        }
+        let before = UNIX_EPOCH - Duration::new(86400, 500000000);
+        assert_eq!(Datum::from_system_time(before), Datum::Time { epoch_time: -86400.5, utc_offset: 0 });
+        assert_eq!(Datum::from_system_time(before).to_system_time(), Some(before));
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn chrono_round_trips() {
+        let time = FixedOffset::east(-3600).ymd(2017, 7, 14).and_hms_milli(1, 40, 0, 250);
+        let datum = Datum::from_chrono(&time);
+        assert_eq!(datum, Datum::Time { epoch_time: 1500000000.25, utc_offset: -3600 });
+        assert_eq!(datum.to_chrono(), Some(time));
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn chrono_rejects_unrepresentable_times() {
+        assert_eq!(Datum::Time { epoch_time: 0.0, utc_offset: 86400 }.to_chrono(), None);
+        assert_eq!(Datum::Time { epoch_time: 0.0, utc_offset: -90000 }.to_chrono(), None);
+        assert_eq!(Datum::Time { epoch_time: 1e20, utc_offset: 0 }.to_chrono(), None);
+        assert_eq!(Datum::Time { epoch_time: -1e20, utc_offset: 0 }.to_chrono(), None);
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::BTreeMap;
+
+extern crate byteorder;
+use self::byteorder::{ByteOrder, LittleEndian};
+
+extern crate hmac;
+use self::hmac::{Hmac, Mac};
+
+extern crate pbkdf2;
+use self::pbkdf2::pbkdf2_hmac;
+
+extern crate sha2;
+use self::sha2::{Digest, Sha256};
+
+extern crate subtle;
+use self::subtle::ConstantTimeEq;
+
+extern crate rand;
+use self::rand::Rng;
+
+extern crate rustc_serialize;
+use self::rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use self::rustc_serialize::json;
+
+use connection::ConnectionError;
+
+const V1_0: u32 = 0x34c2bdc3;
+const PROTOCOL_VERSION: u64 = 0;
+const AUTHENTICATION_METHOD: &'static str = "SCRAM-SHA-256";
+
+// Servers report authentication failures with error codes in this range
+const MIN_AUTH_ERROR_CODE: u64 = 10;
+const MAX_AUTH_ERROR_CODE: u64 = 20;
+
+/// What to do after feeding a server message into a `Handshake`.
+#[derive(Debug, PartialEq)]
+pub enum HandshakeStep {
+    /// Write these bytes to the server, then wait for its next message
+    Send(Vec<u8>),
+    /// Wait for the server's next message
+    Wait,
+    /// The handshake succeeded, and the connection is ready for queries
+    Done,
+}
+
+enum HandshakeState {
+    AwaitingVersion,
+    AwaitingServerFirst,
+    AwaitingServerFinal { server_signature: Vec<u8> },
+    Complete,
+}
+
+/// The client side of the V1_0 handshake, authenticating with SCRAM-SHA-256.
+///
+/// The handshake doesn't do any IO itself. The caller writes out `start()`, then feeds each
+/// null terminated message from the server to `receive` until it returns `Done`.
+pub(crate) struct Handshake {
+    state: HandshakeState,
+    password: String,
+    client_nonce: String,
+    client_first_bare: String,
+}
+
+impl Handshake {
+    pub fn new(user: &str, password: &str) -> Handshake {
+        let mut nonce = [0u8; 18];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        Handshake::with_nonce(user, password, &nonce.to_base64(STANDARD))
+    }
+
+    fn with_nonce(user: &str, password: &str, client_nonce: &str) -> Handshake {
+        Handshake {
+            state: HandshakeState::AwaitingVersion,
+            password: password.to_string(),
+            client_nonce: client_nonce.to_string(),
+            client_first_bare: format!("n={},r={}", escape_username(user), client_nonce),
+        }
+    }
+
+    /// The magic number followed by the first authentication message. The server answers each
+    /// in turn, so they can be sent together.
+    pub fn start(&self) -> Vec<u8> {
+        let mut message = BTreeMap::new();
+        message.insert("protocol_version".to_string(), json::Json::U64(PROTOCOL_VERSION));
+        message.insert("authentication_method".to_string(),
+                       json::Json::String(AUTHENTICATION_METHOD.to_string()));
+        message.insert("authentication".to_string(),
+                       json::Json::String(format!("n,,{}", self.client_first_bare)));
+
+        let mut bytes = vec![0u8; 4];
+        LittleEndian::write_u32(&mut bytes, V1_0);
+        bytes.extend_from_slice(&null_terminated(message));
+        bytes
+    }
+
+    pub fn receive(&mut self, message: &str) -> Result<HandshakeStep, ConnectionError> {
+        let state = ::std::mem::replace(&mut self.state, HandshakeState::Complete);
+        match state {
+            HandshakeState::AwaitingVersion => {
+                let response = try!(parse_server_message(message));
+                let max_version = response.find("max_protocol_version").and_then(|v| v.as_u64());
+                let min_version = response.find("min_protocol_version").and_then(|v| v.as_u64());
+                match (min_version, max_version) {
+                    (Some(min), Some(max)) if (min..=max).contains(&PROTOCOL_VERSION) => {
+                        self.state = HandshakeState::AwaitingServerFirst;
+                        Ok(HandshakeStep::Wait)
+                    },
+                    _ => Err(ConnectionError::RethinkError(format!(
+                        "Unsupported protocol version (server sent {})", message)))
+                }
+            },
+            HandshakeState::AwaitingServerFirst => {
+                let server_first = try!(authentication_field(message));
+                let (client_final, server_signature) = try!(self.client_final(&server_first));
+
+                let mut reply = BTreeMap::new();
+                reply.insert("authentication".to_string(), json::Json::String(client_final));
+                self.state = HandshakeState::AwaitingServerFinal { server_signature: server_signature };
+                Ok(HandshakeStep::Send(null_terminated(reply)))
+            },
+            HandshakeState::AwaitingServerFinal { server_signature } => {
+                let server_final = try!(authentication_field(message));
+                let attributes = parse_attributes(&server_final);
+                let signature = try!(attribute(&attributes, "v")
+                    .and_then(|v| v.from_base64().map_err(|_| invalid_server_message(&server_final))));
+
+                // A server which can't prove it knows the password is an imposter
+                if !bool::from(signature.ct_eq(&server_signature)) {
+                    return Err(ConnectionError::AuthenticationError(
+                        "Invalid server signature".to_string()));
+                }
+                self.state = HandshakeState::Complete;
+                Ok(HandshakeStep::Done)
+            },
+            HandshakeState::Complete => Err(ConnectionError::InvalidOperationError(
+                "The handshake has already completed".into()))
+        }
+    }
+
+    // Answer the server's challenge, returning the client-final message along with the signature
+    // the server must reply with
+    fn client_final(&self, server_first: &str) -> Result<(String, Vec<u8>), ConnectionError> {
+        let attributes = parse_attributes(server_first);
+        let nonce = try!(attribute(&attributes, "r"));
+        if !nonce.starts_with(&self.client_nonce) {
+            return Err(ConnectionError::AuthenticationError(
+                "Invalid nonce from server".to_string()));
+        }
+        let salt = try!(attribute(&attributes, "s")
+            .and_then(|s| s.from_base64().map_err(|_| invalid_server_message(server_first))));
+        let iterations = try!(attribute(&attributes, "i")
+            .and_then(|i| i.parse::<u32>().map_err(|_| invalid_server_message(server_first))));
+        // PBKDF2 needs at least one iteration, and panics without one
+        if iterations == 0 {
+            return Err(ConnectionError::AuthenticationError(
+                "Invalid iteration count from server".to_string()));
+        }
+
+        let salted_password = salted_password(&self.password, &salt, iterations);
+        let client_key = hmac(&salted_password, b"Client Key");
+        let stored_key = Sha256::digest(&client_key);
+
+        let client_final_without_proof = format!("c=biws,r={}", nonce);
+        let auth_message = format!("{},{},{}", self.client_first_bare, server_first,
+                                   client_final_without_proof);
+
+        let client_signature = hmac(&stored_key, auth_message.as_bytes());
+        let client_proof = client_key.iter().zip(client_signature.iter()).map(|(k, s)| {
+            k ^ s
+        }).collect::<Vec<u8>>();
+
+        let server_key = hmac(&salted_password, b"Server Key");
+        let server_signature = hmac(&server_key, auth_message.as_bytes());
+
+        Ok((format!("{},p={}", client_final_without_proof, client_proof.to_base64(STANDARD)),
+            server_signature))
+    }
+}
+
+fn salted_password(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut salted_password = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut salted_password);
+    salted_password
+}
+
+fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+// SCRAM reserves ',' and '=' in usernames
+fn escape_username(user: &str) -> String {
+    user.replace("=", "=3D").replace(",", "=2C")
+}
+
+fn null_terminated(message: BTreeMap<String, json::Json>) -> Vec<u8> {
+    let mut bytes = json::Json::Object(message).to_string().into_bytes();
+    bytes.push(0);
+    bytes
+}
+
+fn invalid_server_message(message: &str) -> ConnectionError {
+    ConnectionError::RethinkError(format!("Invalid handshake message from server: {}", message))
+}
+
+// Parse a message from the server, turning a reported failure into an error
+fn parse_server_message(message: &str) -> Result<json::Json, ConnectionError> {
+    // Servers which don't speak V1_0 reply with a plain string
+    let response = try!(json::Json::from_str(message).map_err(|_| {
+        ConnectionError::RethinkError(message.to_string())
+    }));
+
+    if response.find("success").and_then(|s| s.as_boolean()) == Some(true) {
+        return Ok(response);
+    }
+
+    let error = response.find("error").and_then(|e| e.as_string()).unwrap_or(message).to_string();
+    match response.find("error_code").and_then(|c| c.as_u64()) {
+        Some(code) if MIN_AUTH_ERROR_CODE <= code && code <= MAX_AUTH_ERROR_CODE => {
+            Err(ConnectionError::AuthenticationError(error))
+        },
+        _ => Err(ConnectionError::RethinkError(error))
+    }
+}
+
+fn authentication_field(message: &str) -> Result<String, ConnectionError> {
+    let response = try!(parse_server_message(message));
+    response.find("authentication").and_then(|a| a.as_string()).map(|a| a.to_string()).ok_or_else(|| {
+        invalid_server_message(message)
+    })
+}
+
+// Split a SCRAM message such as "r=abc,s=def,i=4096" into its attributes
+fn parse_attributes(message: &str) -> Vec<(&str, &str)> {
+    message.split(',').filter_map(|attribute| {
+        attribute.find('=').map(|i| (&attribute[..i], &attribute[i + 1..]))
+    }).collect()
+}
+
+fn attribute<'a>(attributes: &[(&str, &'a str)], name: &str) -> Result<&'a str, ConnectionError> {
+    attributes.iter().find(|&&(k, _)| k == name).map(|&(_, v)| v).ok_or_else(|| {
+        ConnectionError::RethinkError(format!("Server handshake message is missing \"{}\"", name))
+    })
+}
+
+// The server's side of the handshake, for servers standing in for RethinkDB in tests. Any user
+// is accepted, and the client's proof isn't checked, but the server proves it knows `password`.
+#[cfg(test)]
+pub fn accept<S: ::std::io::Read + ::std::io::Write>(stream: &mut S, password: &str) -> ::std::io::Result<()> {
+    use std::io::{Error, ErrorKind, Read};
+
+    fn read_message<S: Read>(stream: &mut S) -> ::std::io::Result<String> {
+        let mut bytes = Vec::new();
+        for b in stream.bytes() {
+            match try!(b) {
+                0 => break,
+                b => bytes.push(b)
+            }
+        }
+        let message = try!(json::Json::from_str(&String::from_utf8_lossy(&bytes)).map_err(|e| {
+            Error::new(ErrorKind::InvalidData, e)
+        }));
+        message.find("authentication").and_then(|a| a.as_string()).map(|a| a.to_string()).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidData, "Expected an authentication message")
+        })
+    }
+
+    fn reply(fields: &[(&str, json::Json)]) -> Vec<u8> {
+        let mut message = BTreeMap::new();
+        message.insert("success".to_string(), json::Json::Boolean(true));
+        for &(name, ref value) in fields {
+            message.insert(name.to_string(), value.clone());
+        }
+        null_terminated(message)
+    }
+
+    let mut magic = [0u8; 4];
+    try!(stream.read_exact(&mut magic));
+    if LittleEndian::read_u32(&magic) != V1_0 {
+        return Err(Error::new(ErrorKind::InvalidData, "Expected the V1_0 magic number"));
+    }
+    let client_first = try!(read_message(stream));
+    try!(stream.write_all(&reply(&[("min_protocol_version", json::Json::U64(PROTOCOL_VERSION)),
+                                   ("max_protocol_version", json::Json::U64(PROTOCOL_VERSION))])));
+
+    let client_first_bare = client_first.trim_left_matches("n,,").to_string();
+    let client_nonce = parse_attributes(&client_first_bare).into_iter().find(|&(k, _)| k == "r").map(|(_, v)| v.to_string()).unwrap_or_default();
+    let (salt, iterations) = (b"stand-in salt", 16);
+    let server_first = format!("r={}stand-in,s={},i={}", client_nonce, salt.to_base64(STANDARD), iterations);
+    try!(stream.write_all(&reply(&[("authentication", json::Json::String(server_first.clone()))])));
+
+    let client_final = try!(read_message(stream));
+    let client_final_without_proof = client_final.split(",p=").next().unwrap();
+    let auth_message = format!("{},{},{}", client_first_bare, server_first, client_final_without_proof);
+    let server_key = hmac(&salted_password(password, salt, iterations), b"Server Key");
+    let server_final = format!("v={}", hmac(&server_key, auth_message.as_bytes()).to_base64(STANDARD));
+    stream.write_all(&reply(&[("authentication", json::Json::String(server_final))]))
+}
+
+#[test]
+fn scram_sha_256_exchange() {
+    // The example exchange from RFC 7677
+    let mut handshake = Handshake::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO");
+    assert_eq!(&handshake.start()[..4], &[0xc3, 0xbd, 0xc2, 0x34]);
+
+    let version = r#"{"success":true,"min_protocol_version":0,"max_protocol_version":0,"server_version":"2.3.0"}"#;
+    assert_eq!(handshake.receive(version).unwrap(), HandshakeStep::Wait);
+
+    let server_first = r#"{"success":true,"authentication":"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"}"#;
+    match handshake.receive(server_first).unwrap() {
+        HandshakeStep::Send(bytes) => {
+            let client_final = String::from_utf8(bytes[..bytes.len() - 1].to_vec()).unwrap();
+            assert_eq!(client_final, r#"{"authentication":"c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="}"#);
+        },
+        step => panic!("Expected the client-final message, got {:?}", step)
+    }
+
+    let server_final = r#"{"success":true,"authentication":"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="}"#;
+    assert_eq!(handshake.receive(server_final).unwrap(), HandshakeStep::Done);
+}
+
+#[test]
+fn scram_rejects_bad_server_signature() {
+    let mut handshake = Handshake::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO");
+    handshake.receive(r#"{"success":true,"min_protocol_version":0,"max_protocol_version":0}"#).unwrap();
+    handshake.receive(r#"{"success":true,"authentication":"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"}"#).unwrap();
+
+    let forged = r#"{"success":true,"authentication":"v=AAAATRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="}"#;
+    assert!(matches!(handshake.receive(forged), Err(ConnectionError::AuthenticationError(..))));
+}
+
+#[test]
+fn scram_rejects_zero_iterations() {
+    let mut handshake = Handshake::with_nonce("user", "pencil", "rOprNGfwEbeRWgbNEkqO");
+    handshake.receive(r#"{"success":true,"min_protocol_version":0,"max_protocol_version":0}"#).unwrap();
+
+    let server_first = r#"{"success":true,"authentication":"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,s=W22ZaJ0SNY7soEsUEjb6gQ==,i=0"}"#;
+    assert!(matches!(handshake.receive(server_first), Err(ConnectionError::AuthenticationError(..))));
+}
+
+#[test]
+fn scram_reports_authentication_failure() {
+    let mut handshake = Handshake::with_nonce("user", "wrong", "rOprNGfwEbeRWgbNEkqO");
+    handshake.receive(r#"{"success":true,"min_protocol_version":0,"max_protocol_version":0}"#).unwrap();
+
+    let failure = r#"{"success":false,"error":"Wrong password","error_code":12}"#;
+    assert!(matches!(handshake.receive(failure), Err(ConnectionError::AuthenticationError(ref e)) if e == "Wrong password"));
+}
@@ -3,14 +3,29 @@
 
 extern crate protobuf;
 
+#[macro_use] extern crate serde_derive;
+
 // pub use connection::{Connection};
 
+#[macro_use] mod macros;
+
 pub use rethink::rethink as r;
+pub use serde_datum::{to_datum, from_datum};
+pub use results::{WriteResult, DdlResult, Change};
 
 pub mod rethink;
 pub mod connection;
 pub mod query;
 pub mod datum;
+pub mod serde_datum;
+pub mod results;
 pub mod response;
+pub mod profile;
+pub mod cursor;
+pub mod async_connection;
+pub mod handshake;
+pub mod tls;
+pub mod pool;
+pub mod cluster;
 
 mod ql2;
@@ -0,0 +1,87 @@
+/// Builds a `Datum` with JSON-like syntax.
+///
+/// ```ignore
+/// let doc = datum!{ "id": key, "tags": ["a", "b"], "owner": { "name": name, "age": 42 }, "parent": null };
+/// ```
+///
+/// Objects are written in braces, or as the whole body of the macro, and arrays in brackets.
+/// `null` is `Datum::Null`, and any other value is an expression which is converted with
+/// `Datum::from`. Keys are string literals, or expressions in parentheses.
+#[macro_export]
+macro_rules! datum {
+    // The rest of an object's entries, one key and value at a time
+    (@object $object:ident) => {};
+    // Values which are a single token are inserted straight away, which keeps long literals
+    // under the recursion limit
+    (@object $object:ident $key:tt : $value:tt , $($rest:tt)*) => {
+        $object.insert(::std::string::String::from($key), datum!($value));
+        datum!(@object $object $($rest)*);
+    };
+    (@object $object:ident $key:tt : $value:tt) => {
+        $object.insert(::std::string::String::from($key), datum!($value));
+    };
+    (@object $object:ident $key:tt : $($rest:tt)*) => {
+        datum!(@value $object [$key] () $($rest)*);
+    };
+
+    // An object value runs until the next comma outside of any brackets
+    (@value $object:ident [$key:expr] ($($value:tt)*) , $($rest:tt)*) => {
+        $object.insert(::std::string::String::from($key), datum!($($value)*));
+        datum!(@object $object $($rest)*);
+    };
+    (@value $object:ident [$key:expr] ($($value:tt)*)) => {
+        $object.insert(::std::string::String::from($key), datum!($($value)*));
+    };
+    (@value $object:ident [$key:expr] ($($value:tt)*) $next:tt $($rest:tt)*) => {
+        datum!(@value $object [$key] ($($value)* $next) $($rest)*);
+    };
+
+    // The rest of an array's elements, split on commas the same way
+    (@array $array:ident) => {};
+    (@array $array:ident $element:tt , $($rest:tt)*) => {
+        $array.push(datum!($element));
+        datum!(@array $array $($rest)*);
+    };
+    (@array $array:ident $element:tt) => {
+        $array.push(datum!($element));
+    };
+    (@array $array:ident $($rest:tt)+) => {
+        datum!(@element $array () $($rest)+);
+    };
+    (@element $array:ident ($($element:tt)*) , $($rest:tt)*) => {
+        $array.push(datum!($($element)*));
+        datum!(@array $array $($rest)*);
+    };
+    (@element $array:ident ($($element:tt)*)) => {
+        $array.push(datum!($($element)*));
+    };
+    (@element $array:ident ($($element:tt)*) $next:tt $($rest:tt)*) => {
+        datum!(@element $array ($($element)* $next) $($rest)*);
+    };
+
+    (null) => {
+        $crate::datum::Datum::Null
+    };
+    ([]) => {
+        $crate::datum::Datum::Array(::std::vec::Vec::new())
+    };
+    ([ $($elements:tt)* ]) => {{
+        let mut array = ::std::vec::Vec::new();
+        datum!(@array array $($elements)*);
+        $crate::datum::Datum::Array(array)
+    }};
+    ({ $($entries:tt)* }) => {
+        datum!($($entries)*)
+    };
+    () => {
+        $crate::datum::Datum::Object(::std::collections::HashMap::new())
+    };
+    ($key:tt : $($rest:tt)*) => {{
+        let mut object = ::std::collections::HashMap::new();
+        datum!(@object object $key : $($rest)*);
+        $crate::datum::Datum::Object(object)
+    }};
+    ($value:expr) => {
+        $crate::datum::Datum::from($value)
+    };
+}
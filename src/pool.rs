@@ -0,0 +1,381 @@
+use std::ops::Deref;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use connection::{Connection, ConnectionError};
+use datum::Datum;
+use rethink::rethink;
+
+use ql2::Response_ResponseType;
+
+#[derive(Debug)]
+pub enum PoolError {
+    /// A new connection could not be opened
+    ConnectionError(ConnectionError),
+    /// Every connection stayed checked out for longer than the checkout timeout
+    TimeoutError,
+    /// The pool's minimum size is larger than its maximum, or its maximum is zero
+    InvalidSizeError(String),
+}
+
+impl From<ConnectionError> for PoolError {
+    fn from(e: ConnectionError) -> Self {
+        PoolError::ConnectionError(e)
+    }
+}
+
+struct PoolState {
+    idle: Vec<Connection>,
+    // Idle and checked out connections, plus connections being opened
+    total: usize,
+    // Connections being opened in the background
+    opening: usize,
+    // Why the most recent attempt to open a connection failed
+    error: Option<ConnectionError>,
+}
+
+// The parts of a pool which the threads opening its connections need
+struct Shared {
+    connect: Box<Fn() -> Result<Connection, ConnectionError> + Send + Sync>,
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+/// A pool of connections which can be shared between threads.
+///
+/// Idle connections are checked with a ping before being handed out, and connections which turn
+/// out to be dead are replaced with new ones. New connections are opened in the background, so
+/// a checkout never waits longer than the checkout timeout, however slow the server is to
+/// connect to.
+pub struct Pool {
+    shared: Arc<Shared>,
+    min_size: usize,
+    max_size: usize,
+    checkout_timeout: Duration,
+}
+
+impl Pool {
+    /// Creates a pool which opens connections with `connect`, and keeps at least `min_size` of
+    /// them open. They are opened up front, and connections which die are replaced the next
+    /// time one is checked out.
+    pub fn new<F>(connect: F, min_size: usize, max_size: usize, checkout_timeout: Duration) -> Result<Pool, PoolError>
+        where F: Fn() -> Result<Connection, ConnectionError> + Send + Sync + 'static {
+        if max_size == 0 {
+            return Err(PoolError::InvalidSizeError("A pool needs a max_size of at least 1".to_string()));
+        }
+        if min_size > max_size {
+            return Err(PoolError::InvalidSizeError(
+                format!("min_size ({}) is larger than max_size ({})", min_size, max_size)));
+        }
+
+        let mut idle = Vec::with_capacity(max_size);
+        for _ in 0..min_size {
+            idle.push(try!(connect()));
+        }
+
+        Ok(Pool {
+            shared: Arc::new(Shared {
+                connect: Box::new(connect),
+                state: Mutex::new(PoolState {
+                    total: idle.len(),
+                    idle: idle,
+                    opening: 0,
+                    error: None,
+                }),
+                available: Condvar::new(),
+            }),
+            min_size: min_size,
+            max_size: max_size,
+            checkout_timeout: checkout_timeout,
+        })
+    }
+
+    /// Checks out a connection, waiting up to the checkout timeout for one to be opened or
+    /// returned.
+    pub fn get(&self) -> Result<PooledConnection, PoolError> {
+        let deadline = Instant::now() + self.checkout_timeout;
+        // A half-open socket mustn't hold up the checkout for long
+        let ping_timeout = self.checkout_timeout.min(Duration::from_secs(1));
+        let mut state = self.shared.state.lock().unwrap();
+        // Only failures to open connections for this checkout are reported by it
+        state.error = None;
+        loop {
+            self.refill(&mut state);
+
+            let idle = state.idle.pop();
+            if let Some(connection) = idle {
+                // Don't hold up other threads while pinging the server
+                drop(state);
+                if is_healthy(&connection, ping_timeout) {
+                    return Ok(self.guard(connection));
+                }
+                state = self.shared.state.lock().unwrap();
+                state.total -= 1;
+                continue;
+            }
+
+            if let Some(e) = state.error.take() {
+                return Err(PoolError::from(e));
+            }
+            if state.opening == 0 && state.total < self.max_size {
+                self.open(&mut state);
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(PoolError::TimeoutError);
+            }
+            state = self.shared.available.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    /// The number of connections currently waiting to be checked out.
+    pub fn idle_count(&self) -> usize {
+        self.shared.state.lock().unwrap().idle.len()
+    }
+
+    /// The number of open connections, whether idle or checked out, plus any being opened.
+    pub fn size(&self) -> usize {
+        self.shared.state.lock().unwrap().total
+    }
+
+    // Start opening connections until there are `min_size` of them again, replacing ones which
+    // died
+    fn refill(&self, state: &mut PoolState) {
+        while state.total < self.min_size {
+            self.open(state);
+        }
+    }
+
+    // Open a connection in the background, which goes into the idle list once it's ready
+    fn open(&self, state: &mut PoolState) {
+        state.total += 1;
+        state.opening += 1;
+
+        let shared = self.shared.clone();
+        thread::spawn(move || {
+            let connection = (shared.connect)();
+            let mut state = shared.state.lock().unwrap();
+            state.opening -= 1;
+            match connection {
+                Ok(connection) => state.idle.push(connection),
+                Err(e) => {
+                    state.total -= 1;
+                    state.error = Some(e);
+                }
+            }
+            shared.available.notify_all();
+        });
+    }
+
+    fn guard(&self, connection: Connection) -> PooledConnection {
+        PooledConnection {
+            pool: self,
+            connection: Some(connection),
+        }
+    }
+
+    fn put_back(&self, connection: Connection) {
+        let mut state = self.shared.state.lock().unwrap();
+        // A connection whose socket failed while it was checked out is replaced
+        if connection.is_connected() {
+            state.idle.push(connection);
+        } else {
+            state.total -= 1;
+        }
+        self.shared.available.notify_one();
+    }
+}
+
+fn is_healthy(connection: &Connection, timeout: Duration) -> bool {
+    if !connection.is_open() {
+        return false;
+    }
+    match rethink::expr(Datum::Bool(true)).run_with_timeout(connection, Some(timeout)) {
+        Ok(cursor) => cursor.response_type() == Response_ResponseType::SUCCESS_ATOM,
+        Err(_) => false
+    }
+}
+
+/// A connection checked out of a `Pool`, which goes back to the pool when dropped.
+///
+/// It derefs to a `Connection`, so it can be passed straight to `ReQL::run`. It only gives
+/// shared access, so a borrower can't close the connection or change its default database for
+/// the next one.
+pub struct PooledConnection<'a> {
+    pool: &'a Pool,
+    connection: Option<Connection>,
+}
+
+impl<'a> Deref for PooledConnection<'a> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection.as_ref().unwrap()
+    }
+}
+
+impl<'a> Drop for PooledConnection<'a> {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.pool.put_back(connection);
+        }
+    }
+}
+
+#[test]
+fn pool_reuses_connections() {
+    let pool = Pool::new(rethink::connect_default, 1, 2, Duration::from_secs(1)).unwrap();
+    assert_eq!(pool.size(), 1);
+
+    {
+        let conn = pool.get().unwrap();
+        let mut res = rethink::expr(Datum::String("foo".to_string())).run(&conn).unwrap();
+        assert_eq!(res.next().unwrap().unwrap(), Datum::String("foo".to_string()));
+        assert_eq!(pool.idle_count(), 0);
+    }
+
+    assert_eq!(pool.idle_count(), 1);
+    assert_eq!(pool.size(), 1);
+}
+
+#[test]
+fn pool_checkout_timeout() {
+    let pool = Pool::new(rethink::connect_default, 0, 1, Duration::from_millis(100)).unwrap();
+
+    let _conn = pool.get().unwrap();
+    assert!(matches!(pool.get(), Err(PoolError::TimeoutError)));
+}
+
+// A server standing in for RethinkDB, along with its sockets, which can be shut down to kill
+// the connections to it
+#[cfg(test)]
+fn start_server() -> (u16, Arc<Mutex<Vec<::std::net::TcpStream>>>) {
+    use std::net::TcpListener;
+    use connection::echo_queries;
+    use handshake;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let sockets = Arc::new(Mutex::new(Vec::new()));
+
+    let accepted = sockets.clone();
+    thread::spawn(move || {
+        for socket in listener.incoming() {
+            let mut socket = socket.unwrap();
+            accepted.lock().unwrap().push(socket.try_clone().unwrap());
+            thread::spawn(move || {
+                if handshake::accept(&mut socket, "").is_ok() {
+                    echo_queries(&mut socket);
+                }
+            });
+        }
+    });
+    (port, sockets)
+}
+
+// Shut down every connection to the stand-in server, and wait for the clients to notice
+#[cfg(test)]
+fn kill(sockets: &Mutex<Vec<::std::net::TcpStream>>, connections: &[&Connection]) {
+    use std::net::Shutdown;
+
+    for socket in sockets.lock().unwrap().drain(..) {
+        let _ = socket.shutdown(Shutdown::Both);
+    }
+    while connections.iter().any(|connection| connection.is_connected()) {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn pool_replaces_dead_connections() {
+    let (port, sockets) = start_server();
+    let pool = Pool::new(move || rethink::connect("127.0.0.1", port, None, None, 5), 1, 1, Duration::from_secs(1)).unwrap();
+
+    {
+        let conn = pool.get().unwrap();
+        kill(&sockets, &[&conn]);
+    }
+    assert_eq!(pool.size(), 0);
+
+    let conn = pool.get().unwrap();
+    assert!(conn.is_connected());
+    assert_eq!(pool.size(), 1);
+    assert_eq!(sockets.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn pool_replaces_connections_which_die_while_idle() {
+    let (port, sockets) = start_server();
+    let pool = Pool::new(move || rethink::connect("127.0.0.1", port, None, None, 5), 1, 1, Duration::from_secs(1)).unwrap();
+
+    // The health check finds the dead connection on checkout
+    kill(&sockets, &[]);
+    let conn = pool.get().unwrap();
+    let mut res = rethink::expr(Datum::String("foo".to_string())).run(&conn).unwrap();
+    assert_eq!(res.next().unwrap().unwrap(), Datum::String("foo".to_string()));
+    assert_eq!(pool.size(), 1);
+}
+
+#[test]
+fn pool_refills_to_min_size() {
+    let (port, sockets) = start_server();
+    let pool = Pool::new(move || rethink::connect("127.0.0.1", port, None, None, 5), 2, 3, Duration::from_secs(1)).unwrap();
+
+    {
+        let first = pool.get().unwrap();
+        let second = pool.get().unwrap();
+        kill(&sockets, &[&first, &second]);
+    }
+    assert_eq!(pool.size(), 0);
+
+    // The checkout opens connections back up to the minimum, and takes one of them
+    let _conn = pool.get().unwrap();
+    assert_eq!(pool.size(), 2);
+    while pool.idle_count() < 1 {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(pool.size(), 2);
+}
+
+#[test]
+fn pool_checkout_timeout_covers_opening_connections() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let (port, sockets) = start_server();
+    let reachable = Arc::new(AtomicBool::new(true));
+    let server_reachable = reachable.clone();
+    let pool = Pool::new(move || {
+        if server_reachable.load(Ordering::SeqCst) {
+            rethink::connect("127.0.0.1", port, None, None, 5)
+        } else {
+            // Like connecting to a host which doesn't answer
+            thread::sleep(Duration::from_secs(2));
+            Err(ConnectionError::TimeoutError("Timed out connecting".to_string()))
+        }
+    }, 1, 2, Duration::from_millis(100)).unwrap();
+
+    reachable.store(false, Ordering::SeqCst);
+    {
+        let conn = pool.get().unwrap();
+        kill(&sockets, &[&conn]);
+    }
+
+    let start = Instant::now();
+    assert!(matches!(pool.get(), Err(PoolError::TimeoutError)));
+    assert!(start.elapsed() < Duration::from_secs(1), "The checkout took {:?}", start.elapsed());
+}
+
+#[test]
+fn pool_reports_connection_errors() {
+    let pool = Pool::new(|| Err(ConnectionError::ConnectionError("Connection refused".to_string())),
+                         0, 1, Duration::from_secs(1)).unwrap();
+    assert!(matches!(pool.get(), Err(PoolError::ConnectionError(ConnectionError::ConnectionError(..)))));
+}
+
+#[test]
+fn pool_rejects_invalid_sizes() {
+    assert!(matches!(Pool::new(rethink::connect_default, 2, 1, Duration::from_secs(1)), Err(PoolError::InvalidSizeError(..))));
+    assert!(matches!(Pool::new(rethink::connect_default, 0, 0, Duration::from_secs(1)), Err(PoolError::InvalidSizeError(..))));
+}
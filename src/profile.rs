@@ -0,0 +1,235 @@
+use std::fmt;
+
+extern crate rustc_serialize;
+use self::rustc_serialize::json;
+
+use response::ResponseParseError;
+
+/// A step the server took while running a query with `profile` set.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProfileEvent {
+    /// A step which ran once, with the steps it was made of
+    Task {
+        description: String,
+        duration_ms: f64,
+        sub_tasks: Vec<ProfileEvent>,
+    },
+    /// A step which ran many times, such as a function applied to each row
+    Sample {
+        description: String,
+        mean_duration_ms: f64,
+        samples: u64,
+    },
+    /// Sequences of steps which ran at the same time, such as reads on different shards
+    Parallel(Vec<Vec<ProfileEvent>>),
+}
+
+impl ProfileEvent {
+    /// The wall clock time spent on this step.
+    pub fn duration_ms(&self) -> f64 {
+        match *self {
+            ProfileEvent::Task { duration_ms, .. } => duration_ms,
+            ProfileEvent::Sample { mean_duration_ms, samples, .. } => mean_duration_ms * samples as f64,
+            // Only the slowest branch holds things up
+            ProfileEvent::Parallel(ref branches) => branches.iter().map(|branch| {
+                total_duration_ms(branch)
+            }).fold(0.0, f64::max)
+        }
+    }
+
+    fn from_json(json: json::Json) -> Result<ProfileEvent, ResponseParseError> {
+        let mut o = match json {
+            json::Json::Object(o) => o,
+            _ => return Err("Parse error: profile events should be objects".to_string())
+        };
+
+        if let Some(branches) = o.remove("parallel_tasks") {
+            let branches = match branches {
+                json::Json::Array(branches) => branches,
+                _ => return Err("Parse error: \"parallel_tasks\" of a profile event should be an array".to_string())
+            };
+            return Ok(ProfileEvent::Parallel(try!(branches.into_iter().map(parse_events).collect())));
+        }
+
+        let description = match o.remove("description") {
+            Some(json::Json::String(description)) => description,
+            _ => return Err("Parse error: profile event didn't contain a description".to_string())
+        };
+        if let Some(duration) = o.remove("duration(ms)") {
+            return Ok(ProfileEvent::Task {
+                description: description,
+                duration_ms: try!(number(duration)),
+                sub_tasks: match o.remove("sub_tasks") {
+                    Some(sub_tasks) => try!(parse_events(sub_tasks)),
+                    None => Vec::new()
+                },
+            });
+        }
+        match (o.remove("mean_duration(ms)"), o.remove("n_samples")) {
+            (Some(mean_duration), Some(samples)) => Ok(ProfileEvent::Sample {
+                description: description,
+                mean_duration_ms: try!(number(mean_duration)),
+                samples: try!(count(samples)),
+            }),
+            _ => Err(format!("Parse error: profile event \"{}\" didn't contain a duration", description))
+        }
+    }
+}
+
+/// The profile of a query, as returned when it is run with `profile` set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub events: Vec<ProfileEvent>,
+}
+
+/// Time spent in one step of a query, not counting the steps it was made of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hotspot {
+    /// The descriptions of the step and the steps it was part of, outermost first
+    pub path: Vec<String>,
+    pub self_ms: f64,
+}
+
+impl fmt::Display for Hotspot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.3}ms {}", self.self_ms, self.path.join(" > "))
+    }
+}
+
+impl Profile {
+    pub(crate) fn from_json(json: json::Json) -> Result<Profile, ResponseParseError> {
+        Ok(Profile { events: try!(parse_events(json)) })
+    }
+
+    /// The wall clock time the server spent on the query.
+    pub fn total_ms(&self) -> f64 {
+        total_duration_ms(&self.events)
+    }
+
+    /// Every step of the query with the time spent in it, slowest first. This is the place to
+    /// start when a query is slow.
+    pub fn hotspots(&self) -> Vec<Hotspot> {
+        let mut hotspots = Vec::new();
+        collect_hotspots(&self.events, &mut Vec::new(), &mut hotspots);
+        hotspots.sort_by(|a, b| b.self_ms.partial_cmp(&a.self_ms).unwrap_or(::std::cmp::Ordering::Equal));
+        hotspots
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_events(f, &self.events, 0)
+    }
+}
+
+fn total_duration_ms(events: &[ProfileEvent]) -> f64 {
+    events.iter().map(ProfileEvent::duration_ms).sum()
+}
+
+fn number(json: json::Json) -> Result<f64, ResponseParseError> {
+    match json {
+        json::Json::U64(n) => Ok(n as f64),
+        json::Json::I64(n) => Ok(n as f64),
+        json::Json::F64(n) => Ok(n),
+        _ => Err("Parse error: profile durations should be numbers".to_string())
+    }
+}
+
+fn count(json: json::Json) -> Result<u64, ResponseParseError> {
+    match json {
+        json::Json::U64(n) => Ok(n),
+        json::Json::I64(n) if n >= 0 => Ok(n as u64),
+        json::Json::F64(n) if n >= 0.0 && n.fract() == 0.0 && n < u64::max_value() as f64 => Ok(n as u64),
+        _ => Err("Parse error: profile sample counts should be non-negative integers".to_string())
+    }
+}
+
+fn parse_events(json: json::Json) -> Result<Vec<ProfileEvent>, ResponseParseError> {
+    match json {
+        json::Json::Array(events) => events.into_iter().map(ProfileEvent::from_json).collect(),
+        _ => Err("Parse error: a profile should be an array of events".to_string())
+    }
+}
+
+fn collect_hotspots(events: &[ProfileEvent], path: &mut Vec<String>, hotspots: &mut Vec<Hotspot>) {
+    for event in events {
+        match *event {
+            ProfileEvent::Task { ref description, duration_ms, ref sub_tasks } => {
+                path.push(description.clone());
+                hotspots.push(Hotspot {
+                    path: path.clone(),
+                    self_ms: (duration_ms - total_duration_ms(sub_tasks)).max(0.0),
+                });
+                collect_hotspots(sub_tasks, path, hotspots);
+                path.pop();
+            },
+            ProfileEvent::Sample { ref description, .. } => {
+                path.push(description.clone());
+                hotspots.push(Hotspot { path: path.clone(), self_ms: event.duration_ms() });
+                path.pop();
+            },
+            ProfileEvent::Parallel(ref branches) => {
+                for branch in branches {
+                    collect_hotspots(branch, path, hotspots);
+                }
+            }
+        }
+    }
+}
+
+fn write_events(f: &mut fmt::Formatter, events: &[ProfileEvent], depth: usize) -> fmt::Result {
+    let indent = "  ".repeat(depth);
+    for event in events {
+        match *event {
+            ProfileEvent::Task { ref description, duration_ms, ref sub_tasks } => {
+                try!(writeln!(f, "{}{:.3}ms {}", indent, duration_ms, description));
+                try!(write_events(f, sub_tasks, depth + 1));
+            },
+            ProfileEvent::Sample { ref description, mean_duration_ms, samples } => {
+                try!(writeln!(f, "{}{:.3}ms {} ({} samples, {:.3}ms each)",
+                              indent, event.duration_ms(), description, samples, mean_duration_ms));
+            },
+            ProfileEvent::Parallel(ref branches) => {
+                try!(writeln!(f, "{}{:.3}ms in parallel:", indent, event.duration_ms()));
+                for (i, branch) in branches.iter().enumerate() {
+                    try!(writeln!(f, "{}  branch {}:", indent, i + 1));
+                    try!(write_events(f, branch, depth + 2));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn parses_profile() {
+    let profile = Profile::from_json(json::Json::from_str(r#"[
+        {"description": "Evaluating table.", "duration(ms)": 3.5, "sub_tasks": [
+            {"description": "Evaluating db.", "duration(ms)": 0.5, "sub_tasks": []}
+        ]},
+        {"parallel_tasks": [
+            [{"description": "Perform read on shard.", "duration(ms)": 10, "sub_tasks": []}],
+            [{"description": "Perform read on shard.", "duration(ms)": 4, "sub_tasks": []}]
+        ]},
+        {"description": "Evaluating map.", "mean_duration(ms)": 0.25, "n_samples": 8}
+    ]"#).unwrap()).unwrap();
+
+    assert_eq!(profile.events.len(), 3);
+    assert_eq!(profile.total_ms(), 15.5);
+
+    let hotspots = profile.hotspots();
+    assert_eq!(hotspots[0], Hotspot { path: vec!["Perform read on shard.".to_string()], self_ms: 10.0 });
+    assert_eq!(hotspots[1], Hotspot { path: vec!["Perform read on shard.".to_string()], self_ms: 4.0 });
+    assert_eq!(hotspots[2], Hotspot { path: vec!["Evaluating table.".to_string()], self_ms: 3.0 });
+    assert_eq!(hotspots[3].self_ms, 2.0);
+}
+
+#[test]
+fn rejects_malformed_profile() {
+    assert!(Profile::from_json(json::Json::from_str(r#"[{"description": "Evaluating table."}]"#).unwrap()).is_err());
+    assert!(Profile::from_json(json::Json::from_str(r#"{"description": "Evaluating table."}"#).unwrap()).is_err());
+    for samples in &["2.5", "-1", "1e20", "\"3\""] {
+        let json = format!(r#"[{{"description": "Evaluating map.", "mean_duration(ms)": 0.5, "n_samples": {}}}]"#, samples);
+        assert!(Profile::from_json(json::Json::from_str(&json).unwrap()).is_err(), "Accepted {} samples", samples);
+    }
+}
@@ -1,10 +1,21 @@
 use std::collections::hash_map::HashMap;
+use std::time::Duration;
 
-use datum::Datum;
+use datum::{Datum, DecodeOptions, Number, SerializeError, quote};
 use connection::{Connection, SendError};
-use response::{ResponseParseError, RethinkResponse};
+use response::{ResponseParseError, ReqlError, Frame};
+use cursor::Cursor;
+use serde_datum::{DatumError, to_datum, from_datum};
+use async_connection::{AsyncConnection, AsyncCursor, AsyncResponse};
 
-use ql2::Term_TermType;
+extern crate futures;
+use self::futures::{future, Future};
+
+extern crate serde;
+use self::serde::Serialize;
+use self::serde::de::DeserializeOwned;
+
+use ql2::{Term_TermType, Response_ResponseType};
 
 
 wrapped_enum!{
@@ -14,11 +25,17 @@ wrapped_enum!{
         /// An error sending the query
         SendError(SendError),
         /// An error decoding the query
-        ParseError(ResponseParseError)
+        ParseError(ResponseParseError),
+        /// An error the server reported for the query
+        ReqlError(ReqlError),
+        /// The query couldn't be turned into JSON
+        SerializeError(SerializeError),
+        /// A result didn't have the shape of the type it was decoded into
+        DecodeError(DatumError)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ReQL {
     Term {
         command: Term_TermType,
@@ -40,27 +57,156 @@ pub mod internal {
 }
 
 impl ReQL {
-    pub fn run(&self, connection: &mut Connection) -> Result<RethinkResponse, RunQueryError> {
-        let string_reql = self.serialize_query_for_connection(connection);
-        let json = try!(connection.send(&string_reql));
-        Ok(try!(RethinkResponse::from_json(json)))
+    pub fn run<'a>(&self, connection: &'a Connection) -> Result<Cursor<'a>, RunQueryError> {
+        self.run_with_timeout(connection, connection.timeout())
+    }
+
+    /// Like `run`, but waits up to `timeout` for each response instead of the connection's
+    /// timeout. `None` waits forever.
+    pub fn run_with_timeout<'a>(&self, connection: &'a Connection, timeout: Option<Duration>) -> Result<Cursor<'a>, RunQueryError> {
+        let string_reql = try!(self.serialize_query_for_connection(connection));
+        Cursor::start(connection, self, &string_reql, timeout, false, DecodeOptions::default())
+    }
+
+    /// Like `run`, with options which apply to the whole query. A query run with `noreply` returns
+    /// as soon as it has been written, with a cursor which has no results.
+    pub fn run_with_options<'a>(&self, connection: &'a Connection, options: &RunOptions) -> Result<Cursor<'a>, RunQueryError> {
+        let params = connection.serialize_params_with(&try!(options.optargs()));
+        let string_reql = try!(self.serialize_query_with_params(&params));
+        if options.noreply {
+            try!(connection.send_noreply(&string_reql));
+            return Ok(Cursor::finished(connection, self));
+        }
+        Cursor::start(connection, self, &string_reql, connection.timeout(), false, options.decode_options())
+    }
+
+    /// Runs the query and decodes its result into `T`. An atom is decoded as it is, and a
+    /// sequence is read to the end and decoded as an array, so `T` should be a `Vec` or similar.
+    /// Don't use this for changefeeds, which never end.
+    pub fn run_as<T: DeserializeOwned>(&self, connection: &Connection) -> Result<T, RunQueryError> {
+        let mut cursor = try!(self.run(connection));
+        let datum = if cursor.response_type() == Response_ResponseType::SUCCESS_ATOM {
+            try!(cursor.next().unwrap_or(Ok(Datum::Null)))
+        } else {
+            Datum::Array(try!(cursor.collect::<Result<Vec<_>, _>>()))
+        };
+        Ok(try!(from_datum(datum)))
+    }
+
+    /// Like `run`, but the cursor decodes each result into `T` as it is read.
+    pub fn run_cursor<'a, T: DeserializeOwned>(&self, connection: &'a Connection) -> Result<Cursor<'a, T>, RunQueryError> {
+        let string_reql = try!(self.serialize_query_for_connection(connection));
+        Cursor::start_as(connection, self, &string_reql, connection.timeout(), false, DecodeOptions::default())
+    }
+
+    /// Like `run`, for queries which only read. If the connection has a reconnect policy and
+    /// breaks while the query is in flight, the query is run again on the new connection.
+    pub fn run_read<'a>(&self, connection: &'a Connection) -> Result<Cursor<'a>, RunQueryError> {
+        let string_reql = try!(self.serialize_query_for_connection(connection));
+        Cursor::start(connection, self, &string_reql, connection.timeout(), true, DecodeOptions::default())
+    }
+
+    /// Shorthand for `run_with_options` with only `noreply` set.
+    pub fn run_noreply(&self, connection: &Connection) -> Result<(), RunQueryError> {
+        self.run_with_options(connection, &RunOptions { noreply: true, ..Default::default() }).map(|_| ())
+    }
+
+    /// Runs the query without blocking. The future resolves to the atom the query returned, or to
+    /// a stream of its results for sequences and cursors.
+    pub fn run_async(&self, connection: &AsyncConnection) -> Box<Future<Item = AsyncResponse, Error = RunQueryError>> {
+        let string_reql = match self.serialize_query_with_params(&connection.serialize_params()) {
+            Ok(string_reql) => string_reql,
+            Err(e) => return Box::new(future::err(RunQueryError::from(e)))
+        };
+        AsyncCursor::start(connection.clone(), self.clone(), &string_reql)
+    }
+
+    // Errors reported by the server are rendered against the query which caused them
+    pub(crate) fn attach_to(&self, error: RunQueryError) -> RunQueryError {
+        match error {
+            RunQueryError::ReqlError(e) => RunQueryError::ReqlError(e.with_query(self.clone())),
+            other => other
+        }
+    }
+
+    /// Renders the query the way it would be written with this driver, along with the byte range
+    /// of the sub-term `backtrace` leads to, if there is one.
+    pub fn render(&self, backtrace: &[Frame]) -> (String, Option<(usize, usize)>) {
+        let mut out = String::new();
+        let mut span = None;
+        self.render_into(&mut out, Some(backtrace), &mut span);
+        (out, span)
+    }
+
+    fn render_into(&self, out: &mut String, target: Option<&[Frame]>, span: &mut Option<(usize, usize)>) {
+        let start = out.len();
+        match *self {
+            ReQL::Term { ref command, ref arguments, ref optional_arguments } => {
+                let name = format!("{:?}", command).to_lowercase();
+                // Terms which take another term first are written as methods on it
+                let (receiver, rest) = match arguments.first() {
+                    Some(first @ &ReQL::Term { .. }) => (Some(first), &arguments[1..]),
+                    _ => (None, &arguments[..])
+                };
+                let offset = if receiver.is_some() { 1 } else { 0 };
+
+                match receiver {
+                    Some(receiver) => receiver.render_into(out, descend(target, &Frame::Pos(0)), span),
+                    None => out.push_str("r")
+                }
+                out.push_str(&format!(".{}(", name));
+                for (i, argument) in rest.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    argument.render_into(out, descend(target, &Frame::Pos(i + offset)), span);
+                }
+
+                if !optional_arguments.is_empty() {
+                    if !rest.is_empty() {
+                        out.push_str(", ");
+                    }
+                    out.push_str("{");
+                    let mut names = optional_arguments.keys().collect::<Vec<_>>();
+                    names.sort();
+                    for (i, name) in names.into_iter().enumerate() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        out.push_str(&format!("{}: ", quote(name)));
+                        render_datum(&optional_arguments[name], out, descend(target, &Frame::Opt(name.clone())), span);
+                    }
+                    out.push_str("}");
+                }
+                out.push_str(")");
+            },
+            ReQL::Datum(ref datum) => render_datum(datum, out, target, span)
+        }
+
+        if target.map_or(false, |t| t.is_empty()) {
+            *span = Some((start, out.len()));
+        }
     }
 
     // TODO(zach): Do not expose
-    pub fn serialize_query_for_connection(&self, connection: &Connection) -> String {
-        format!("[1,{},{}]", self.serialize(), connection.serialize_params())
+    pub fn serialize_query_for_connection(&self, connection: &Connection) -> Result<String, SerializeError> {
+        self.serialize_query_with_params(&connection.serialize_params())
+    }
+
+    pub(crate) fn serialize_query_with_params(&self, params: &str) -> Result<String, SerializeError> {
+        Ok(format!("[1,{},{}]", try!(self.serialize()), params))
     }
 
     // TODO(zach): Do not expose
-    pub fn serialize(&self) -> String {
+    pub fn serialize(&self) -> Result<String, SerializeError> {
         match self {
             &ReQL::Term{ref command,
                         ref arguments,
                         ref optional_arguments} => {
                             let command_string = (*command as u32).to_string();
-                            let arguments_string = format!("[{}]", arguments.iter().map(|a| {
+                            let arguments_string = format!("[{}]", try!(arguments.iter().map(|a| {
                                 a.serialize()
-                            }).collect::<Vec<_>>().connect(","));
+                            }).collect::<Result<Vec<_>, _>>()).join(","));
 
                             let mut parts = vec!(command_string, arguments_string);
 
@@ -69,14 +215,14 @@ impl ReQL {
                             if !optional_arguments.is_empty() {
                                 let optional_arguments_string = format!(
                                     "{{{}}}",
-                                    optional_arguments.iter().map(|(option_name, option_val)| {
-                                        format!("\"{}\":{}", option_name, option_val.serialize())
-                                    }).collect::<Vec<_>>().connect(",")
+                                    try!(optional_arguments.iter().map(|(option_name, option_val)| {
+                                        option_val.serialize().map(|value| format!("{}:{}", quote(option_name), value))
+                                    }).collect::<Result<Vec<_>, _>>()).join(",")
                                 );
 
                                 parts.push(optional_arguments_string);
                             }
-                            format!("[{}]", parts.connect(","))
+                            Ok(format!("[{}]", parts.join(",")))
                         },
             &ReQL::Datum(ref d) => d.serialize()
         }
@@ -98,20 +244,68 @@ impl ReQL {
         }
     }
 
-    pub fn insert(&self, document: &Datum, durability: Option<Durability>) -> ReQL {
+    /// Inserts a document, which can be a `Datum` or any type that implements `Serialize`. Fails
+    /// if the document can't be converted into a datum.
+    pub fn insert<T: Serialize + ?Sized>(&self, document: &T, durability: Option<Durability>) -> Result<ReQL, SerializeError> {
         let mut optional_arguments = HashMap::new();
         if let Some(d) = durability {
             optional_arguments.insert("durability".to_string(), Datum::String(d.serialize()));
         }
 
-        ReQL::Term {
+        let document = try!(to_datum(document).map_err(SerializeError::InvalidDocument));
+        Ok(ReQL::Term {
             command: Term_TermType::INSERT,
-            arguments: vec![self.clone(), ReQL::Datum(document.clone())],
+            arguments: vec![self.clone(), ReQL::Datum(document)],
             optional_arguments: optional_arguments
-        }
+        })
+    }
+}
+
+// Follow one frame of a backtrace, if the target is still below this term
+// Renders a datum as JSON. Arrays and objects are sent as terms of their own, so a backtrace can
+// lead to one of their elements or fields.
+fn render_datum(datum: &Datum, out: &mut String, target: Option<&[Frame]>, span: &mut Option<(usize, usize)>) {
+    let start = out.len();
+    match *datum {
+        Datum::Array(ref elements) => {
+            out.push_str("[");
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(",");
+                }
+                render_datum(element, out, descend(target, &Frame::Pos(i)), span);
+            }
+            out.push_str("]");
+        },
+        Datum::Object(ref fields) => {
+            out.push_str("{");
+            let mut names = fields.keys().collect::<Vec<_>>();
+            names.sort();
+            for (i, name) in names.into_iter().enumerate() {
+                if i > 0 {
+                    out.push_str(",");
+                }
+                out.push_str(&format!("{}:", quote(name)));
+                render_datum(&fields[name], out, descend(target, &Frame::Opt(name.clone())), span);
+            }
+            out.push_str("}");
+        },
+        _ => out.push_str(&datum.to_json().to_string())
+    }
+
+    if target.map_or(false, |t| t.is_empty()) {
+        *span = Some((start, out.len()));
     }
 }
 
+fn descend<'a>(target: Option<&'a [Frame]>, frame: &Frame) -> Option<&'a [Frame]> {
+    match target {
+        Some(frames) if frames.first() == Some(frame) => Some(&frames[1..]),
+        _ => None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Durability {
     Hard,
     Soft
@@ -125,3 +319,119 @@ impl Durability {
         }.to_string()
     }
 }
+
+/// Which replicas a read may be answered from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadMode {
+    /// The primary replica, which may return changes that are not yet safely committed
+    Single,
+    /// A majority of replicas, so only safely committed changes are returned
+    Majority,
+    /// Any replica, which may return stale data
+    Outdated
+}
+
+impl ReadMode {
+    fn serialize(&self) -> String {
+        match *self {
+            ReadMode::Single => "single",
+            ReadMode::Majority => "majority",
+            ReadMode::Outdated => "outdated"
+        }.to_string()
+    }
+}
+
+/// How a pseudo-type such as a time, binary string or grouped result is returned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Converted to its native representation
+    Native,
+    /// Left as the raw object the server sent
+    Raw
+}
+
+impl Format {
+    fn serialize(&self) -> String {
+        match *self {
+            Format::Native => "native",
+            Format::Raw => "raw"
+        }.to_string()
+    }
+}
+
+/// Options which apply to a whole query, sent as the global optargs of its START query.
+///
+/// Options left as `None` or `false` are not sent, so the server's defaults apply.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunOptions {
+    pub read_mode: Option<ReadMode>,
+    /// The durability of every write in the query which doesn't set its own
+    pub durability: Option<Durability>,
+    /// Whether the server should return a profile of the query
+    pub profile: Option<bool>,
+    /// The largest array the server will build in memory
+    pub array_limit: Option<u64>,
+    pub time_format: Option<Format>,
+    pub group_format: Option<Format>,
+    pub binary_format: Option<Format>,
+    /// The fewest rows the server will send in a batch
+    pub min_batch_rows: Option<u64>,
+    /// The most bytes the server will send in a batch
+    pub max_batch_bytes: Option<u64>,
+    /// How much smaller the first batch is than the rest
+    pub first_batch_scaleback_factor: Option<f64>,
+    /// Send the query without waiting for the server to answer. Errors in the query itself are
+    /// never reported; use `Connection::noreply_wait` to wait for the server to finish.
+    pub noreply: bool,
+}
+
+impl RunOptions {
+    // How the results of a query run with these options are decoded
+    pub(crate) fn decode_options(&self) -> DecodeOptions {
+        DecodeOptions {
+            raw_time: self.time_format == Some(Format::Raw),
+            raw_binary: self.binary_format == Some(Format::Raw),
+            raw_groups: self.group_format == Some(Format::Raw),
+        }
+    }
+
+    // The options which are set, as names and serialized values
+    pub(crate) fn optargs(&self) -> Result<Vec<(&'static str, String)>, SerializeError> {
+        let string = |value: String| quote(&value);
+        let mut optargs = Vec::new();
+        if let Some(read_mode) = self.read_mode {
+            optargs.push(("read_mode", string(read_mode.serialize())));
+        }
+        if let Some(durability) = self.durability {
+            optargs.push(("durability", string(durability.serialize())));
+        }
+        if let Some(profile) = self.profile {
+            optargs.push(("profile", profile.to_string()));
+        }
+        if let Some(array_limit) = self.array_limit {
+            optargs.push(("array_limit", array_limit.to_string()));
+        }
+        if let Some(format) = self.time_format {
+            optargs.push(("time_format", string(format.serialize())));
+        }
+        if let Some(format) = self.group_format {
+            optargs.push(("group_format", string(format.serialize())));
+        }
+        if let Some(format) = self.binary_format {
+            optargs.push(("binary_format", string(format.serialize())));
+        }
+        if let Some(rows) = self.min_batch_rows {
+            optargs.push(("min_batch_rows", rows.to_string()));
+        }
+        if let Some(bytes) = self.max_batch_bytes {
+            optargs.push(("max_batch_bytes", bytes.to_string()));
+        }
+        if let Some(factor) = self.first_batch_scaleback_factor {
+            optargs.push(("first_batch_scaleback_factor", try!(Datum::Number(Number::Float(factor)).serialize())));
+        }
+        if self.noreply {
+            optargs.push(("noreply", true.to_string()));
+        }
+        Ok(optargs)
+    }
+}
@@ -1,25 +1,153 @@
+use std::fmt;
+
 extern crate rustc_serialize;
 use self::rustc_serialize::json;
 
-use datum::Datum;
+use datum::{Datum, DecodeOptions};
+use query::ReQL;
+use profile::Profile;
 
-use ql2::Response_ResponseType;
+use ql2::{Response_ResponseType, Response_ResponseNote, Response_ErrorType, Frame_FrameType};
 
 
 pub type ResponseParseError = String;
 
+/// One step along the path from the root of a query to the term which caused an error.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+    /// The argument at this position
+    Pos(usize),
+    /// The optional argument with this name
+    Opt(String),
+}
+
+impl Frame {
+    pub fn frame_type(&self) -> Frame_FrameType {
+        match *self {
+            Frame::Pos(..) => Frame_FrameType::POS,
+            Frame::Opt(..) => Frame_FrameType::OPT
+        }
+    }
+}
+
+/// The message and location of an error reported by the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrorDetails {
+    pub message: String,
+    /// The path to the term which failed, outermost first. Empty if the server didn't say.
+    pub backtrace: Vec<Frame>,
+    /// The query which failed, when it is known
+    pub query: Option<ReQL>,
+}
+
+/// An error the server reported instead of running (or finishing) the query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReqlError {
+    /// The driver sent something the server couldn't make sense of
+    ClientError(ErrorDetails),
+    /// The query is malformed, such as a term with the wrong number of arguments
+    CompileError(ErrorDetails),
+    /// The query failed while it was running. The error type is `None` for servers too old to
+    /// report one.
+    RuntimeError(Option<Response_ErrorType>, ErrorDetails),
+}
+
+impl ReqlError {
+    pub fn message(&self) -> &str {
+        &self.details().message
+    }
+
+    pub fn backtrace(&self) -> &[Frame] {
+        &self.details().backtrace
+    }
+
+    /// What kind of runtime error this is, such as `NON_EXISTENCE` for a missing table or
+    /// `OP_INDETERMINATE` for a write which may or may not have happened.
+    pub fn error_type(&self) -> Option<Response_ErrorType> {
+        match *self {
+            ReqlError::RuntimeError(error_type, _) => error_type,
+            _ => None
+        }
+    }
+
+    /// The query which failed, so it can be shown along with the error.
+    pub fn query(&self) -> Option<&ReQL> {
+        self.details().query.as_ref()
+    }
+
+    pub(crate) fn with_query(mut self, query: ReQL) -> ReqlError {
+        self.details_mut().query = Some(query);
+        self
+    }
+
+    fn details(&self) -> &ErrorDetails {
+        match *self {
+            ReqlError::ClientError(ref details) => details,
+            ReqlError::CompileError(ref details) => details,
+            ReqlError::RuntimeError(_, ref details) => details
+        }
+    }
+
+    fn details_mut(&mut self) -> &mut ErrorDetails {
+        match *self {
+            ReqlError::ClientError(ref mut details) => details,
+            ReqlError::CompileError(ref mut details) => details,
+            ReqlError::RuntimeError(_, ref mut details) => details
+        }
+    }
+}
+
+/// Shows the message and, when the query is known, the query with the failing part underlined:
+///
+/// ```text
+/// Runtime error (NON_EXISTENCE): Table `test.missing` does not exist. in:
+/// r.db("test").table("missing").get("id")
+/// ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^
+/// ```
+impl fmt::Display for ReqlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReqlError::ClientError(..) => try!(write!(f, "Client error")),
+            ReqlError::CompileError(..) => try!(write!(f, "Compile error")),
+            ReqlError::RuntimeError(Some(error_type), _) => try!(write!(f, "Runtime error ({:?})", error_type)),
+            ReqlError::RuntimeError(None, _) => try!(write!(f, "Runtime error"))
+        }
+        try!(write!(f, ": {}", self.message()));
+
+        if let Some(query) = self.query() {
+            let (rendered, span) = query.render(self.backtrace());
+            try!(write!(f, " in:\n{}", rendered));
+            if let Some((start, end)) = span {
+                let column = rendered[..start].chars().count();
+                let width = rendered[start..end].chars().count();
+                try!(write!(f, "\n{}{}", " ".repeat(column), "^".repeat(width)));
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct RethinkResponse {
     pub response_type: Response_ResponseType,
     pub result: Vec<Datum>,
-    pub backtrace: Option<Vec<String>>,
-    // profile: ???,
-    // notes: Vec<notes>,
+    pub backtrace: Option<Vec<Frame>>,
+    /// The kind of runtime error, for `RUNTIME_ERROR` responses
+    pub error_type: Option<Response_ErrorType>,
+    /// Where the server spent its time, for queries run with `profile` set. `None` if the
+    /// server's profile wasn't in a shape this driver understands.
+    pub profile: Option<Profile>,
+    /// Extra information about the result, such as the kind of changefeed it is
+    pub notes: Vec<Response_ResponseNote>,
 }
 
 impl RethinkResponse {
     // TODO(zach): Do not expose
     pub fn from_json(json: json::Json) -> Result<RethinkResponse, ResponseParseError> {
+        RethinkResponse::from_json_with(json, &DecodeOptions::default())
+    }
+
+    pub(crate) fn from_json_with(json: json::Json, options: &DecodeOptions) -> Result<RethinkResponse, ResponseParseError> {
         // TODO(zach): this is so unreadable
         if let json::Json::Object(mut o) = json {
             return Ok(RethinkResponse {
@@ -34,30 +162,64 @@ impl RethinkResponse {
                 },
                 result: match o.remove("r") {
                     Some(json::Json::Array(json_array)) => json_array.into_iter().map(|json| {
-                        Datum::from_json(json)
+                        Datum::from_json_with(json, options)
                     }).collect(),
                     Some(..) => return Err("Parse error: \"r\" field of rethink response should be an array".to_string()),
                     None => return Err("Parse error: rethink response didn't contain a response field".to_string())
                 },
-                backtrace: o.remove("b").and_then(|backtrace_json| {
-                    match backtrace_json {
-                        json::Json::Array(backtrace_array) => {
-                            Some(backtrace_array.into_iter().map(|backtace_item| {
-                                // TODO(zach): I hope these are strings!
-                                match backtace_item {
-                                    json::Json::String(s) => s,
-                                    _ => "".to_string()
-                                }
-                            }).collect())
-                        },
-                        _ => None
-                    }
-                })
+                backtrace: match o.remove("b") {
+                    Some(json::Json::Array(frames)) => Some(try!(frames.into_iter().map(|frame| {
+                        match frame {
+                            json::Json::U64(n) => Ok(Frame::Pos(n as usize)),
+                            json::Json::I64(n) if n >= 0 => Ok(Frame::Pos(n as usize)),
+                            json::Json::String(s) => Ok(Frame::Opt(s)),
+                            _ => Err("Parse error: backtrace frames should be positions or optarg names".to_string())
+                        }
+                    }).collect())),
+                    Some(..) => return Err("Parse error: \"b\" field of rethink response should be an array".to_string()),
+                    None => None
+                },
+                // Newer servers may send kinds of error this driver doesn't know about, which
+                // mustn't hide the error itself
+                error_type: o.remove("e").and_then(|e| e.as_i64()).and_then(|n| {
+                    ::protobuf::ProtobufEnum::from_i32(n as i32)
+                }),
+                // The profile is only for diagnostics, so one which doesn't parse mustn't lose
+                // the results
+                profile: o.remove("p").and_then(|profile_json| Profile::from_json(profile_json).ok()),
+                notes: match o.remove("n") {
+                    // Notes this driver doesn't know about yet are left out
+                    Some(json::Json::Array(notes)) => notes.into_iter().filter_map(|note| {
+                        note.as_i64().and_then(|n| ::protobuf::ProtobufEnum::from_i32(n as i32))
+                    }).collect(),
+                    Some(..) => return Err("Parse error: \"n\" field of rethink response should be an array".to_string()),
+                    None => Vec::new()
+                }
             })
         } else {
             return Err("Parse error: expected the rethink json response to be an object".to_string())
         }
     }
+
+    /// Turns an error response into a `ReqlError`, passing any other response through.
+    pub(crate) fn into_result(self) -> Result<RethinkResponse, ReqlError> {
+        let details = |response: RethinkResponse| ErrorDetails {
+            message: match response.result.into_iter().next() {
+                Some(Datum::String(message)) => message,
+                Some(other) => other.to_json().to_string(),
+                None => String::new()
+            },
+            backtrace: response.backtrace.unwrap_or(Vec::new()),
+            query: None,
+        };
+        let response_type = self.response_type;
+        match response_type {
+            Response_ResponseType::CLIENT_ERROR => Err(ReqlError::ClientError(details(self))),
+            Response_ResponseType::COMPILE_ERROR => Err(ReqlError::CompileError(details(self))),
+            Response_ResponseType::RUNTIME_ERROR => Err(ReqlError::RuntimeError(self.error_type, details(self))),
+            _ => Ok(self)
+        }
+    }
 }
 
 #[test]
@@ -65,5 +227,150 @@ fn deserialize_response() {
     let resp = RethinkResponse::from_json(json::Json::from_str(r###"{"t":1,"r":["foo"],"n":[]}"###).unwrap()).unwrap();
     assert!(resp.response_type == Response_ResponseType::SUCCESS_ATOM);
     assert!(resp.result.first().unwrap() == &Datum::String("foo".to_string()));
-    assert!(resp.backtrace == Option::None)
+    assert!(resp.backtrace == Option::None);
+    assert!(resp.profile == Option::None);
+    assert!(resp.notes.is_empty())
+}
+
+#[test]
+fn deserialize_runtime_error() {
+    let resp = RethinkResponse::from_json(json::Json::from_str(r###"{"t":18,"e":3100000,"r":["Table `test.missing` does not exist."],"b":[0,"index",1]}"###).unwrap()).unwrap();
+    let error = resp.into_result().unwrap_err();
+    assert_eq!(error, ReqlError::RuntimeError(Some(Response_ErrorType::NON_EXISTENCE), ErrorDetails {
+        message: "Table `test.missing` does not exist.".to_string(),
+        backtrace: vec![Frame::Pos(0), Frame::Opt("index".to_string()), Frame::Pos(1)],
+        query: None,
+    }));
+    assert_eq!(error.backtrace()[1].frame_type(), Frame_FrameType::OPT);
+}
+
+#[test]
+fn deserialize_unknown_error_type() {
+    let resp = RethinkResponse::from_json(json::Json::from_str(r###"{"t":18,"e":9900000,"r":["Something new went wrong."],"b":[]}"###).unwrap()).unwrap();
+    let error = resp.into_result().unwrap_err();
+    assert_eq!(error, ReqlError::RuntimeError(None, ErrorDetails {
+        message: "Something new went wrong.".to_string(),
+        backtrace: Vec::new(),
+        query: None,
+    }));
+}
+
+#[test]
+fn error_marks_failing_term() {
+    use rethink::rethink;
+
+    let query = rethink::db("test").table("missing").get("id");
+    let error = ReqlError::RuntimeError(Some(Response_ErrorType::OP_FAILED), ErrorDetails {
+        message: "Table `test.missing` does not exist.".to_string(),
+        backtrace: vec![Frame::Pos(0)],
+        query: None,
+    });
+    assert_eq!(error.to_string(), "Runtime error (OP_FAILED): Table `test.missing` does not exist.");
+
+    let error = error.with_query(query);
+    assert_eq!(error.to_string(), concat!(
+        "Runtime error (OP_FAILED): Table `test.missing` does not exist. in:\n",
+        "r.db(\"test\").table(\"missing\").get(\"id\")\n",
+        "^^^^^^^^^^^^^^^^^^^^^^^^^^^^^"));
+}
+
+#[test]
+fn error_marks_failing_argument() {
+    use rethink::rethink;
+
+    let query = rethink::db("test").table("users").get("id");
+    let error = ReqlError::CompileError(ErrorDetails {
+        message: "Expected type STRING.".to_string(),
+        backtrace: vec![Frame::Pos(0), Frame::Pos(1)],
+        query: Some(query),
+    });
+    assert_eq!(error.to_string(), concat!(
+        "Compile error: Expected type STRING. in:\n",
+        "r.db(\"test\").table(\"users\").get(\"id\")\n",
+        "                   ^^^^^^^"));
+}
+
+#[test]
+fn error_marks_failing_optarg() {
+    use rethink::rethink;
+    use query::Durability;
+
+    let query = rethink::db("test").table("users").insert(&Datum::from_str(r#"{"id": 1}"#), Some(Durability::Soft)).unwrap();
+    let error = ReqlError::RuntimeError(Some(Response_ErrorType::LOGIC), ErrorDetails {
+        message: "Durability option `soft` unrecognized.".to_string(),
+        backtrace: vec![Frame::Opt("durability".to_string())],
+        query: Some(query),
+    });
+    assert_eq!(error.to_string(), concat!(
+        "Runtime error (LOGIC): Durability option `soft` unrecognized. in:\n",
+        "r.db(\"test\").table(\"users\").insert({\"id\":1}, {\"durability\": \"soft\"})\n",
+        "                                                            ^^^^^^"));
+}
+
+#[test]
+fn error_marks_failing_datum_element() {
+    use rethink::rethink;
+
+    let query = rethink::expr(Datum::from_str(r#"[1, {"a": [true, "x"], "b": 2}]"#));
+    let error = ReqlError::RuntimeError(Some(Response_ErrorType::LOGIC), ErrorDetails {
+        message: "Expected type BOOL but found STRING.".to_string(),
+        backtrace: vec![Frame::Pos(1), Frame::Opt("a".to_string()), Frame::Pos(1)],
+        query: Some(query),
+    });
+    assert_eq!(error.to_string(), concat!(
+        "Runtime error (LOGIC): Expected type BOOL but found STRING. in:\n",
+        "[1,{\"a\":[true,\"x\"],\"b\":2}]\n",
+        "              ^^^"));
+}
+
+#[test]
+fn error_marks_field_inside_optarg() {
+    use std::collections::HashMap;
+    use ql2::Term_TermType;
+    use query::ReQL;
+
+    let mut optional_arguments = HashMap::new();
+    optional_arguments.insert("default".to_string(), Datum::from_str(r#"{"name": "anonymous", "tags": []}"#));
+    let query = ReQL::Term {
+        command: Term_TermType::GET_FIELD,
+        arguments: vec![ReQL::Datum(Datum::from_str(r#"{}"#)), ReQL::Datum(Datum::String("user".to_string()))],
+        optional_arguments: optional_arguments,
+    };
+    let error = ReqlError::RuntimeError(Some(Response_ErrorType::LOGIC), ErrorDetails {
+        message: "Expected type STRING but found ARRAY.".to_string(),
+        backtrace: vec![Frame::Opt("default".to_string()), Frame::Opt("tags".to_string())],
+        query: Some(query),
+    });
+    assert_eq!(error.to_string(), concat!(
+        "Runtime error (LOGIC): Expected type STRING but found ARRAY. in:\n",
+        "r.get_field({}, \"user\", {\"default\": {\"name\":\"anonymous\",\"tags\":[]}})\n",
+        "                                                               ^^"));
+}
+
+#[test]
+fn deserialize_compile_error() {
+    let resp = RethinkResponse::from_json(json::Json::from_str(r###"{"t":17,"r":["Expected 2 arguments but found 1."],"b":[]}"###).unwrap()).unwrap();
+    let error = resp.into_result().unwrap_err();
+    assert!(matches!(error, ReqlError::CompileError(..)));
+    assert_eq!(error.error_type(), None);
+    assert_eq!(error.message(), "Expected 2 arguments but found 1.");
+}
+
+#[test]
+fn deserialize_feed_notes() {
+    let resp = RethinkResponse::from_json(json::Json::from_str(r###"{"t":3,"r":[],"n":[1,5,99]}"###).unwrap()).unwrap();
+    assert_eq!(resp.notes, vec![Response_ResponseNote::SEQUENCE_FEED, Response_ResponseNote::INCLUDES_STATES]);
+}
+
+#[test]
+fn deserialize_profiled_response() {
+    let resp = RethinkResponse::from_json(json::Json::from_str(r###"{"t":1,"r":[1],"p":[{"description":"Evaluating datum.","duration(ms)":0.002,"sub_tasks":[]}]}"###).unwrap()).unwrap();
+    assert_eq!(resp.profile.unwrap().events.len(), 1);
+}
+
+#[test]
+fn deserialize_unexpected_profile() {
+    let resp = RethinkResponse::from_json(json::Json::from_str(r###"{"t":1,"r":[1],"p":{"total":"fast"}}"###).unwrap()).unwrap();
+    assert_eq!(resp.result, vec![Datum::from_str("1")]);
+    assert!(resp.profile.is_none());
 }
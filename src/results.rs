@@ -0,0 +1,59 @@
+//! Typed forms of the documents the server returns for writes and for changes to databases,
+//! tables and indexes, for use with `ReQL::run_as`.
+
+use datum::Datum;
+
+/// A document before and after a change.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Change {
+    /// `None` if the document was created
+    #[serde(default)]
+    pub old_val: Option<Datum>,
+    /// `None` if the document was deleted
+    #[serde(default)]
+    pub new_val: Option<Datum>,
+}
+
+/// The result of `insert`, `update`, `replace` and `delete`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WriteResult {
+    pub inserted: u64,
+    pub replaced: u64,
+    pub unchanged: u64,
+    pub skipped: u64,
+    pub deleted: u64,
+    pub errors: u64,
+    /// The first error the server hit, if `errors` isn't zero
+    pub first_error: Option<String>,
+    /// The primary keys the server made up for inserted documents which didn't have one
+    pub generated_keys: Vec<String>,
+    pub warnings: Vec<String>,
+    /// Only returned when the write was run with `return_changes`
+    pub changes: Vec<Change>,
+}
+
+/// The result of creating, dropping or reconfiguring databases, tables and indexes. Only the
+/// counters which apply to the command that was run will be non-zero.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DdlResult {
+    pub dbs_created: u64,
+    pub dbs_dropped: u64,
+    pub tables_created: u64,
+    pub tables_dropped: u64,
+    /// Indexes created by `index_create`
+    pub created: u64,
+    /// Indexes dropped by `index_drop`
+    pub dropped: u64,
+    /// Indexes renamed by `index_rename`
+    pub renamed: u64,
+    pub reconfigured: u64,
+    pub rebalanced: u64,
+    /// Tables which `wait` waited for
+    pub ready: u64,
+    /// The configurations of the databases or tables before and after the command
+    pub config_changes: Vec<Change>,
+    /// The statuses of the tables before and after the command
+    pub status_changes: Vec<Change>,
+}
@@ -4,9 +4,18 @@ pub mod rethink {
     use ql2::Term_TermType;
 
     use connection::{Connection, ConnectionError};
+    use async_connection::AsyncConnection;
+    use tls::TlsOptions;
+    use cluster::{Cluster, ClusterError};
     use query::ReQL;
     use datum::Datum;
 
+    extern crate futures;
+    use self::futures::Future;
+
+    extern crate tokio_core;
+    use self::tokio_core::reactor::Handle;
+
     pub fn connect_default() -> Result<Connection, ConnectionError> {
         connect("localhost", 28015, None, None, 20)
     }
@@ -17,6 +26,30 @@ pub mod rethink {
         Ok(c)
     }
 
+    pub fn connect_with_user(host: &str, port: u16, default_db: Option<&str>, user: &str, password: &str, timeout_secs: u32) -> Result<Connection, ConnectionError> {
+        let mut c = Connection::new_with_user(host, port, default_db, user, password, timeout_secs);
+        try!(c.connect());
+        Ok(c)
+    }
+
+    pub fn connect_tls(host: &str, port: u16, default_db: Option<&str>, user: &str, password: &str, tls: TlsOptions, timeout_secs: u32) -> Result<Connection, ConnectionError> {
+        let mut c = Connection::new_with_user(host, port, default_db, user, password, timeout_secs);
+        c.use_tls(Some(tls));
+        try!(c.connect());
+        Ok(c)
+    }
+
+    pub fn connect_cluster(seeds: &[(&str, u16)], default_db: Option<&str>, user: &str, password: &str, timeout_secs: u32) -> Result<Cluster, ClusterError> {
+        let cluster = Cluster::new(seeds, default_db, user, password, timeout_secs);
+        try!(cluster.connect());
+        Ok(cluster)
+    }
+
+    pub fn connect_async(host: &str, port: u16, default_db: Option<&str>, auth_key: Option<&str>, handle: &Handle)
+        -> Box<Future<Item = AsyncConnection, Error = ConnectionError>> {
+        AsyncConnection::connect(host, port, default_db, auth_key, handle)
+    }
+
     pub fn db_create(db_name: &str) -> ReQL {
         ReQL::Term {
             command: Term_TermType::DB_CREATE,
@@ -53,6 +86,15 @@ pub mod rethink {
         }
     }
 
+    /// Binary data, to be stored or compared against binary data in the database.
+    pub fn binary(data: &[u8]) -> ReQL {
+        ReQL::Term {
+            command: Term_TermType::BINARY,
+            arguments: vec![ReQL::Datum(Datum::Binary(data.to_vec()))],
+            optional_arguments: HashMap::new()
+        }
+    }
+
     pub fn table(table_name: &str) -> ReQL {
         ReQL::Term {
             command: Term_TermType::TABLE,
@@ -70,13 +112,34 @@ mod tests {
 
     use ql2::Term_TermType;
     use ql2::Response_ResponseType;
+    use ql2::Response_ResponseNote;
+    use ql2::Response_ErrorType;
 
-    use datum::Datum;
-    use query::ReQL;
+    use datum::{Datum, Number, SerializeError};
+    use query::{ReQL, RunOptions, ReadMode, Durability, Format, RunQueryError};
+    use response::ReqlError;
+    use results::{WriteResult, DdlResult};
+    use connection::{Connection, ConnectionError, ReconnectPolicy};
+    use tls::TlsOptions;
+
+    use std::io;
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
 
     extern crate rand;
     use self::rand::Rng;
 
+    extern crate byteorder;
+
+    extern crate futures;
+    use self::futures::Stream;
+
+    extern crate tokio_core;
+    use self::tokio_core::reactor::Core;
+
+    use async_connection::AsyncResponse;
+
     #[test]
     fn serialize_reql() {
         let mut options = HashMap::new();
@@ -89,7 +152,7 @@ mod tests {
             optional_arguments: options
         };
 
-        let serialized = reql.serialize();
+        let serialized = reql.serialize().unwrap();
         println!("{}", serialized);
         assert!(serialized == r##"[1,["foo"],{"bar":"hello","baz":true}]"## ||
                 serialized == r##"[1,["foo"],{"baz":true,"bar":"hello"}]"##)
@@ -118,10 +181,318 @@ mod tests {
         assert!(conn.is_open());
     }
 
+    #[test]
+    fn wrong_password() {
+        match rethink::connect_with_user("localhost", 28015, None, "admin", "not the password", 20) {
+            Err(ConnectionError::AuthenticationError(..)) => {},
+            Err(e) => panic!("Expected an authentication error, got {:?}", e),
+            Ok(..) => panic!("Connected with the wrong password")
+        }
+    }
+
+    #[test]
+    fn tls_to_plain_server_fails() {
+        // The server doesn't speak TLS, so the TLS handshake fails instead of hanging
+        assert!(rethink::connect_tls("localhost", 28015, None, "admin", "", TlsOptions::default(), 20).is_err());
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        // A server which accepts connections but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        match rethink::connect("127.0.0.1", port, None, None, 1) {
+            Err(ConnectionError::TimeoutError(..)) => {},
+            Err(e) => panic!("Expected a timeout, got {:?}", e),
+            Ok(..) => panic!("Connected to a server which never answered")
+        }
+    }
+
+    #[test]
+    fn query_timeout() {
+        use std::time::Duration;
+        use connection::SendError;
+
+        let conn = rethink::connect_default().unwrap();
+        // JavaScript which runs for the full ten seconds the server allows it
+        let mut optional_arguments = HashMap::new();
+        optional_arguments.insert("timeout".to_string(), Datum::Number(Number::UInt(10)));
+        let spin = ReQL::Term {
+            command: Term_TermType::JAVASCRIPT,
+            arguments: vec![ReQL::string("while(true){}")],
+            optional_arguments: optional_arguments
+        };
+        match spin.run_with_timeout(&conn, Some(Duration::from_millis(200))) {
+            Err(RunQueryError::SendError(SendError::TimeoutError)) => {},
+            Err(e) => panic!("Expected a timeout, got {:?}", e),
+            Ok(..) => panic!("Expected the query to time out")
+        }
+
+        // The connection is still usable afterwards, without waiting for the query to finish
+        let started = ::std::time::Instant::now();
+        assert!(rethink::db_list().run(&conn).is_ok());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    // A connection to a server which stops reading after the handshake, along with the server's
+    // socket
+    fn stalled_connection() -> (Connection, TcpStream) {
+        use handshake;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            handshake::accept(&mut socket, "").unwrap();
+            socket
+        });
+        let conn = rethink::connect("127.0.0.1", port, None, None, 1).unwrap();
+        (conn, server.join().unwrap())
+    }
+
+    #[test]
+    fn write_timeout_breaks_connection() {
+        use connection::SendError;
+
+        // Too big for the socket buffers, so only part of the frame is written
+        let big = rethink::expr(Datum::String("x".repeat(32 << 20)));
+
+        let (conn, _socket) = stalled_connection();
+        match big.run(&conn) {
+            Err(RunQueryError::SendError(SendError::TimeoutError)) => {},
+            Err(e) => panic!("Expected a timeout, got {:?}", e),
+            Ok(..) => panic!("Expected the write to time out")
+        }
+        // Anything sent after the partial frame would be misread, so the connection is unusable
+        assert!(!conn.is_connected());
+        match rethink::expr(Datum::Bool(true)).run(&conn) {
+            Err(RunQueryError::SendError(SendError::ClosedConnectionError)) => {},
+            Err(e) => panic!("Expected the connection to be closed, got {:?}", e),
+            Ok(..) => panic!("Sent a query after a partial frame")
+        };
+
+        // The same goes for queries which aren't waiting on a response
+        let (conn, _socket) = stalled_connection();
+        assert!(matches!(big.run_noreply(&conn), Err(RunQueryError::SendError(SendError::TimeoutError))));
+        assert!(!conn.is_connected());
+    }
+
+    // Answers each query with the next of `responses`, sending the first batch of a cursor and then failing
+    fn scripted_server(responses: Vec<&'static str>) -> u16 {
+        use handshake;
+        use connection::encode_query;
+        use self::byteorder::{LittleEndian, ReadBytesExt};
+        use std::io::{Read, Write};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            handshake::accept(&mut socket, "").unwrap();
+            for response in responses {
+                let token = socket.read_u64::<LittleEndian>().unwrap();
+                let mut query = vec![0u8; socket.read_u32::<LittleEndian>().unwrap() as usize];
+                socket.read_exact(&mut query).unwrap();
+                socket.write_all(&encode_query(token, response)).unwrap();
+            }
+            // Hold the connection open, leaving any further queries unanswered, until the client
+            // is done with it
+            let _ = io::copy(&mut socket, &mut io::sink());
+        });
+        port
+    }
+
+    const PARTIAL_BATCH: &'static str = r#"{"t":3,"r":[1]}"#;
+    const FAILED_BATCH: &'static str = r#"{"t":18,"e":4100000,"r":["Something went wrong."],"b":[]}"#;
+
+    fn assert_rendered_against_table(error: RunQueryError) {
+        match error {
+            RunQueryError::ReqlError(error) => {
+                assert_eq!(error.error_type(), Some(Response_ErrorType::OP_FAILED));
+                assert!(error.to_string().contains("in:\nr.db(\"test\").table(\"t\")\n"),
+                        "Expected the query in {:?}", error.to_string());
+            },
+            e => panic!("Expected a ReqlError, got {:?}", e)
+        }
+    }
+
+    #[test]
+    fn dropping_cursor_does_not_wait_for_server() {
+        use std::sync::mpsc::channel;
+        use std::time::Duration;
+
+        // The server never answers the STOP
+        let port = scripted_server(vec![PARTIAL_BATCH]);
+        let conn = rethink::connect("127.0.0.1", port, None, None, 0).unwrap();
+
+        let (dropped, wait) = channel();
+        thread::spawn(move || {
+            let mut cursor = rethink::db("test").table("t").run(&conn).unwrap();
+            assert!(matches!(cursor.next(), Some(Ok(Datum::Number(Number::UInt(1))))));
+            drop(cursor);
+            dropped.send(()).unwrap();
+        });
+        wait.recv_timeout(Duration::from_secs(5)).expect("Dropping the cursor waited for the server");
+    }
+
+    #[test]
+    fn later_batch_errors_render_query() {
+        let port = scripted_server(vec![PARTIAL_BATCH, FAILED_BATCH]);
+        let conn = rethink::connect("127.0.0.1", port, None, None, 5).unwrap();
+
+        let mut cursor = rethink::db("test").table("t").run(&conn).unwrap();
+        assert!(matches!(cursor.next(), Some(Ok(Datum::Number(Number::UInt(1))))));
+        match cursor.next() {
+            Some(Err(e)) => assert_rendered_against_table(e),
+            other => panic!("Expected the second batch to fail, got {:?}", other)
+        };
+        assert!(cursor.next().is_none());
+    }
+
+    #[test]
+    fn later_batch_errors_render_query_async() {
+        let port = scripted_server(vec![PARTIAL_BATCH, FAILED_BATCH]);
+        let mut core = Core::new().unwrap();
+        let conn = core.run(rethink::connect_async("127.0.0.1", port, None, None, &core.handle())).unwrap();
+
+        match core.run(rethink::db("test").table("t").run_async(&conn)).unwrap() {
+            AsyncResponse::Stream(cursor) => assert_rendered_against_table(core.run(cursor.collect()).unwrap_err()),
+            AsyncResponse::Atom(..) => panic!("Expected a stream of rows")
+        }
+    }
+
+    #[test]
+    fn noreply_option_does_not_wait_for_server() {
+        // The server never answers, so waiting would time out
+        let (conn, _socket) = stalled_connection();
+        let options = RunOptions { noreply: true, ..Default::default() };
+        let mut cursor = rethink::expr(Datum::Bool(true)).run_with_options(&conn, &options).unwrap();
+        assert!(cursor.is_complete());
+        assert!(cursor.next().is_none());
+    }
+
+    // Forwards connections to the server, until the returned sockets are shut down
+    fn start_proxy() -> (u16, Arc<Mutex<Vec<TcpStream>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sockets = Arc::new(Mutex::new(Vec::new()));
+
+        let accepted = sockets.clone();
+        thread::spawn(move || {
+            for client in listener.incoming() {
+                let client = client.unwrap();
+                let server = TcpStream::connect(("localhost", 28015)).unwrap();
+                accepted.lock().unwrap().push(client.try_clone().unwrap());
+                accepted.lock().unwrap().push(server.try_clone().unwrap());
+                pipe(client.try_clone().unwrap(), server.try_clone().unwrap());
+                pipe(server, client);
+            }
+        });
+        (port, sockets)
+    }
+
+    fn pipe(mut from: TcpStream, mut to: TcpStream) {
+        thread::spawn(move || {
+            let _ = io::copy(&mut from, &mut to);
+            let _ = to.shutdown(Shutdown::Both);
+        });
+    }
+
+    fn cut(sockets: &Mutex<Vec<TcpStream>>) {
+        for socket in sockets.lock().unwrap().drain(..) {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+    }
+
+    #[test]
+    fn reconnects_after_socket_breaks() {
+        let (port, sockets) = start_proxy();
+        let mut conn = Connection::new("127.0.0.1", port, None, None, 20);
+        conn.set_reconnect_policy(Some(ReconnectPolicy::default()));
+        conn.connect().unwrap();
+
+        cut(&sockets);
+        let mut res = rethink::expr(Datum::String("foo".to_string())).run_read(&conn).unwrap();
+        assert_eq!(res.next().unwrap().unwrap(), Datum::String("foo".to_string()));
+    }
+
+    #[test]
+    fn reconnects_share_one_attempt_budget() {
+        use handshake;
+        use std::io::Read;
+        use std::time::Duration;
+
+        // Every other connection fails its handshake, and the rest break as soon as a query arrives
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = Arc::new(Mutex::new(0));
+        let counted = accepted.clone();
+        thread::spawn(move || {
+            for (i, socket) in listener.incoming().enumerate() {
+                let mut socket = socket.unwrap();
+                *counted.lock().unwrap() += 1;
+                if i % 2 == 0 && handshake::accept(&mut socket, "").is_ok() {
+                    let _ = socket.read(&mut [0u8; 1]);
+                }
+            }
+        });
+
+        let mut conn = Connection::new("127.0.0.1", port, None, None, 5);
+        conn.set_reconnect_policy(Some(ReconnectPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(1),
+        }));
+        conn.connect().unwrap();
+
+        assert!(rethink::expr(Datum::Bool(true)).run_read(&conn).is_err());
+        // Not counting the first connection
+        assert_eq!(*accepted.lock().unwrap() - 1, 5);
+    }
+
+    #[test]
+    fn no_reconnect_without_policy() {
+        let (port, sockets) = start_proxy();
+        let mut conn = Connection::new("127.0.0.1", port, None, None, 20);
+        conn.connect().unwrap();
+
+        cut(&sockets);
+        assert!(rethink::expr(Datum::Bool(true)).run_read(&conn).is_err());
+
+        conn.reconnect().unwrap();
+        assert!(rethink::expr(Datum::Bool(true)).run_read(&conn).is_ok());
+    }
+
+    #[test]
+    fn cluster_discovers_servers() {
+        let cluster = rethink::connect_cluster(&[("localhost", 28015)], None, "admin", "", 20).unwrap();
+        let servers = cluster.servers();
+        assert!(!servers.is_empty());
+
+        let mut res = cluster.run(&rethink::expr(Datum::String("foo".to_string()))).unwrap();
+        assert!(servers.iter().any(|&(ref name, _)| Some(&name[..]) == res.server()));
+        assert_eq!(res.next().unwrap().unwrap(), Datum::String("foo".to_string()));
+    }
+
+    #[test]
+    fn cluster_skips_unreachable_servers() {
+        // Nothing listens on the first seed
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dead_port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let cluster = rethink::connect_cluster(&[("127.0.0.1", dead_port), ("localhost", 28015)], None, "admin", "", 20).unwrap();
+        for _ in 0..4 {
+            let mut res = cluster.run_read(&rethink::expr(Datum::Bool(true))).unwrap();
+            assert_eq!(res.next().unwrap().unwrap(), Datum::Bool(true));
+        }
+    }
+
     #[test]
     fn test_expr() {
         let conn = rethink::connect_default().unwrap();
-        assert_eq!(rethink::expr(Datum::String("foo".to_string())).serialize_query_for_connection(&conn),
+        assert_eq!(rethink::expr(Datum::String("foo".to_string())).serialize_query_for_connection(&conn).unwrap(),
                    r##"[1,"foo",{}]"##)
     }
 
@@ -132,10 +503,10 @@ mod tests {
         let tablename = "__test_tablename";
         let dbname = "__test_dbname";
 
-        assert_eq!(rethink::table(tablename).serialize_query_for_connection(&conn),
+        assert_eq!(rethink::table(tablename).serialize_query_for_connection(&conn).unwrap(),
                    format!("[1,[15,[\"{}\"]],{{}}]", tablename));
 
-        assert_eq!(rethink::db(dbname).table(tablename).serialize_query_for_connection(&conn),
+        assert_eq!(rethink::db(dbname).table(tablename).serialize_query_for_connection(&conn).unwrap(),
                    format!("[1,[15,[[14,[\"{}\"]],\"{}\"]],{{}}]", dbname, tablename))
     }
 
@@ -155,71 +526,99 @@ mod tests {
     fn sends_default_db() {
         let mut conn = rethink::connect_default().unwrap();
         conn.use_(Some("default_db_name"));
-        assert_eq!(rethink::expr(Datum::String("foo".to_string())).serialize_query_for_connection(&conn),
+        assert_eq!(rethink::expr(Datum::String("foo".to_string())).serialize_query_for_connection(&conn).unwrap(),
                    r##"[1,"foo",{"db":[14,["default_db_name"]]}]"##)
     }
 
     #[test]
     fn test_db() {
         let mut conn = rethink::connect_default().unwrap();
-        let resp = rethink::db("test").run(&mut conn).unwrap();
-        assert_eq!(resp.response_type, Response_ResponseType::RUNTIME_ERROR);
-        assert_eq!(resp.result.first().unwrap(), &Datum::String("Query result must be of type DATUM, GROUPED_DATA, or STREAM (got DATABASE).".to_string()));
+        match rethink::db("test").run(&mut conn) {
+            Err(RunQueryError::ReqlError(error @ ReqlError::RuntimeError(..))) => {
+                assert_eq!(error.error_type(), Some(Response_ErrorType::LOGIC));
+                assert_eq!(error.message(), "Query result must be of type DATUM, GROUPED_DATA, or STREAM (got DATABASE).");
+            },
+            other => panic!("expected a runtime error, got {:?}", other.map(|_| ()))
+        };
+    }
+
+    #[test]
+    fn missing_table_error() {
+        let conn = rethink::connect_default().unwrap();
+        match rethink::db("test").table("no_such_table").run(&conn) {
+            Err(RunQueryError::ReqlError(error @ ReqlError::RuntimeError(..))) => {
+                assert!(error.message().contains("does not exist"));
+                assert!(error.backtrace().is_empty());
+                assert!(error.to_string().ends_with(concat!(
+                    "in:\nr.db(\"test\").table(\"no_such_table\")\n",
+                    "^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^")));
+            },
+            other => panic!("expected a runtime error, got {:?}", other.map(|_| ()))
+        };
+    }
+
+    // Setup which the server may refuse, such as dropping a database which doesn't exist yet.
+    // Anything else going wrong fails the test.
+    fn allow_reql_error<T>(result: Result<T, RunQueryError>) {
+        match result {
+            Ok(..) | Err(RunQueryError::ReqlError(..)) => {},
+            Err(e) => panic!("setup failed: {:?}", e)
+        }
     }
 
     #[test]
     fn create_db() {
         let mut conn = rethink::connect_default().unwrap();
-        rethink::db_drop("db_create_test").run(&mut conn).unwrap();
-        let res = rethink::db_create("db_create_test").run(&mut conn).unwrap();
-        match res.response_type {
+        allow_reql_error(rethink::db_drop("db_create_test").run(&mut conn));
+        let mut res = rethink::db_create("db_create_test").run(&mut conn).unwrap();
+        match res.response_type() {
             Response_ResponseType::SUCCESS_ATOM => {
-                match res.result.first().unwrap() {
-                    &Datum::Object(ref o) => {
+                match res.next().unwrap().unwrap() {
+                    Datum::Object(ref o) => {
                         let json_create_count = o.get("dbs_created").unwrap();
                         match json_create_count {
-                            &Datum::Number(n) => assert!(n.floor() as u64 == 1),
-                            _ => panic!("unrecognized response: {:?}", res)
+                            &Datum::Number(n) => assert!(n.as_f64().floor() as u64 == 1),
+                            _ => panic!("unrecognized response: {:?}", o)
                         }
                     }
-                    _ => panic!("unrecognized response: {:?}", res)
+                    other => panic!("unrecognized response: {:?}", other)
                 }
             }
-            _ => panic!("got an unexpected response type: {:?}", res.response_type)
+            other => panic!("got an unexpected response type: {:?}", other)
         }
     }
 
     #[test]
     fn drop_db() {
         let mut conn = rethink::connect_default().unwrap();
-        rethink::db_create("db_drop_test").run(&mut conn).unwrap();
-        let res = rethink::db_drop("db_drop_test").run(&mut conn).unwrap();
-        match res.response_type {
+        allow_reql_error(rethink::db_create("db_drop_test").run(&mut conn));
+        let mut res = rethink::db_drop("db_drop_test").run(&mut conn).unwrap();
+        match res.response_type() {
             Response_ResponseType::SUCCESS_ATOM => {
-                match res.result.first().unwrap() {
-                    &Datum::Object(ref o) => {
+                match res.next().unwrap().unwrap() {
+                    Datum::Object(ref o) => {
                         let json_create_count = o.get("dbs_dropped").unwrap();
                         match json_create_count {
-                            &Datum::Number(n) => assert!(n.floor() as u64 == 1),
-                            _ => panic!("unrecognized response: {:?}", res)
+                            &Datum::Number(n) => assert!(n.as_f64().floor() as u64 == 1),
+                            _ => panic!("unrecognized response: {:?}", o)
                         }
                     }
-                    _ => panic!("unrecognized response: {:?}", res)
+                    other => panic!("unrecognized response: {:?}", other)
                 }
             }
-            _ => panic!("got an unexpected response type: {:?}", res.response_type)
+            other => panic!("got an unexpected response type: {:?}", other)
         }
     }
 
     #[test]
     fn list_db() {
         let mut conn = rethink::connect_default().unwrap();
-        rethink::db_create("db_list_test1").run(&mut conn).unwrap();
-        rethink::db_create("db_list_test2").run(&mut conn).unwrap();
-        rethink::db_create("db_list_test3").run(&mut conn).unwrap();
-        let res = rethink::db_list().run(&mut conn).unwrap();
-        match res.result.first().unwrap() {
-            &Datum::Array(ref db_names) => assert!(db_names.contains(&Datum::String("db_list_test1".to_string()))),
+        allow_reql_error(rethink::db_create("db_list_test1").run(&mut conn));
+        allow_reql_error(rethink::db_create("db_list_test2").run(&mut conn));
+        allow_reql_error(rethink::db_create("db_list_test3").run(&mut conn));
+        let mut res = rethink::db_list().run(&mut conn).unwrap();
+        match res.next().unwrap().unwrap() {
+            Datum::Array(ref db_names) => assert!(db_names.contains(&Datum::String("db_list_test1".to_string()))),
             _ => panic!("Expected an array of database names")
         }
     }
@@ -234,22 +633,417 @@ mod tests {
         let table_query = rethink::db("test").table("test_table");
 
         let value = Datum::from_str(&format!(r###"{{"id": "{}", "value": 42}}"###, key));
-        let insert_result = table_query.insert(&value, None).run(&mut conn).unwrap();
+        let mut insert_result = table_query.insert(&value, None).unwrap().run(&mut conn).unwrap();
         // println!("serialized: {}", table_query.insert(&value, Some(Durability::Soft)).serialize_query_for_connection(&conn));
-        assert!(insert_result.response_type == Response_ResponseType::SUCCESS_ATOM);
-        match insert_result.result.first().unwrap() {
-            &Datum::Object(ref o) => {
+        assert!(insert_result.response_type() == Response_ResponseType::SUCCESS_ATOM);
+        match insert_result.next().unwrap().unwrap() {
+            Datum::Object(ref o) => {
                 if let &Datum::Number(n) = o.get("inserted").unwrap() {
-                    assert_eq!(n as u64, 1)
+                    assert_eq!(n.as_f64() as u64, 1)
                 } else {
                     panic!("Unexpected type of \"inserted\" metadata")
                 }
             },
             _ => panic!("Unexpected type in response")
         }
+        drop(insert_result);
+
+        let mut get_result = table_query.get(&key).run(&mut conn).unwrap();
+        assert!(get_result.response_type() == Response_ResponseType::SUCCESS_ATOM);
+        assert_eq!(get_result.next().unwrap().unwrap(), value)
+    }
+
+    #[test]
+    fn ddl_results() {
+        let conn = rethink::connect_default().unwrap();
+        allow_reql_error(rethink::db_drop("db_ddl_result_test").run(&conn));
+
+        let created: DdlResult = rethink::db_create("db_ddl_result_test").run_as(&conn).unwrap();
+        assert_eq!(created.dbs_created, 1);
+        assert_eq!(created.config_changes.len(), 1);
+        assert!(created.config_changes[0].old_val.is_none());
+
+        let db_names: Vec<String> = rethink::db_list().run_as(&conn).unwrap();
+        assert!(db_names.contains(&"db_ddl_result_test".to_string()));
 
-        let get_result = table_query.get(&key).run(&mut conn).unwrap();
-        assert!(get_result.response_type == Response_ResponseType::SUCCESS_ATOM);
-        assert_eq!(*get_result.result.first().unwrap(), value)
+        let dropped: DdlResult = rethink::db_drop("db_ddl_result_test").run_as(&conn).unwrap();
+        assert_eq!(dropped.dbs_dropped, 1);
+        assert_eq!(dropped.config_changes.len(), 1);
+        assert!(dropped.config_changes[0].new_val.is_none());
+    }
+
+    #[test]
+    fn insert_write_result() {
+        let conn = rethink::connect_default().unwrap();
+
+        let mut rng = rand::thread_rng();
+        let key = rng.next_u64().to_string();
+
+        let table_query = rethink::db("test").table("test_table");
+        let value = Datum::from_str(&format!(r#"{{"id": "{}", "value": 42}}"#, key));
+        let written: WriteResult = table_query.insert(&value, None).unwrap().run_as(&conn).unwrap();
+        assert_eq!(written.inserted, 1);
+        assert_eq!(written.errors, 0);
+        assert!(written.generated_keys.is_empty());
+
+        let row: Datum = table_query.get(&key).run_as(&conn).unwrap();
+        assert_eq!(row, value);
+    }
+
+    #[test]
+    fn insert_datum_macro() {
+        let conn = rethink::connect_default().unwrap();
+
+        let mut rng = rand::thread_rng();
+        let key = rng.next_u64().to_string();
+
+        let table_query = rethink::db("test").table("test_table");
+        let value = datum!{ "id": key.clone(), "value": 42, "tags": ["a", "b"], "parent": null };
+        table_query.insert(&value, None).unwrap().run(&conn).unwrap();
+
+        let mut get_result = table_query.get(&key).run(&conn).unwrap();
+        assert_eq!(get_result.next().unwrap().unwrap(), value);
+    }
+
+    #[test]
+    fn noreply_insert() {
+        let conn = rethink::connect_default().unwrap();
+
+        let mut rng = rand::thread_rng();
+        let key = rng.next_u64().to_string();
+        let value = Datum::from_str(&format!(r###"{{"id": "{}", "value": "noreply"}}"###, key));
+
+        let table_query = rethink::db("test").table("test_table");
+        table_query.insert(&value, None).unwrap().run_noreply(&conn).unwrap();
+        conn.noreply_wait().unwrap();
+
+        let mut get_result = table_query.get(&key).run(&conn).unwrap();
+        assert_eq!(get_result.next().unwrap().unwrap(), value)
+    }
+
+    #[test]
+    fn serializes_noreply() {
+        let conn = Connection::new("localhost", 28015, Some("default_db_name"), None, 20);
+        let options = RunOptions { noreply: true, ..Default::default() };
+        assert_eq!(conn.serialize_params_with(&options.optargs().unwrap()),
+                   r##"{"db":[14,["default_db_name"]],"noreply":true}"##)
+    }
+
+    #[test]
+    fn insert_array() {
+        let conn = rethink::connect_default().unwrap();
+
+        let mut rng = rand::thread_rng();
+        let key = rng.next_u64().to_string();
+        let value = Datum::from_str(&format!(r###"{{"id": "{}", "tags": ["a", ["b"], {{"c": []}}]}}"###, key));
+
+        let table_query = rethink::db("test").table("test_table");
+        table_query.insert(&value, None).unwrap().run(&conn).unwrap();
+        let mut get_result = table_query.get(&key).run(&conn).unwrap();
+        assert_eq!(get_result.next().unwrap().unwrap(), value)
+    }
+
+    #[test]
+    fn rejects_nan() {
+        let conn = Connection::new("localhost", 28015, None, None, 20);
+        assert!(matches!(rethink::expr(Datum::Number(Number::Float(::std::f64::NAN))).run(&conn),
+                         Err(RunQueryError::SerializeError(..))));
+    }
+
+    #[test]
+    fn rejects_unconvertible_document() {
+        // Objects need string keys
+        let mut document = HashMap::new();
+        document.insert(vec![1], true);
+        assert!(matches!(rethink::db("test").table("test_table").insert(&document, None),
+                         Err(SerializeError::InvalidDocument(..))));
+    }
+
+    #[test]
+    fn time_round_trip() {
+        use std::time::{Duration, SystemTime};
+
+        let conn = rethink::connect_default().unwrap();
+        let now = ReQL::Term {
+            command: Term_TermType::NOW,
+            arguments: vec![],
+            optional_arguments: HashMap::new()
+        };
+        let server_now = now.run(&conn).unwrap().next().unwrap().unwrap().to_system_time().unwrap();
+        let skew = match server_now.duration_since(SystemTime::now()) {
+            Ok(ahead) => ahead,
+            Err(behind) => behind.duration()
+        };
+        assert!(skew < Duration::from_secs(60));
+
+        let time = Datum::Time { epoch_time: 1500000000.5, utc_offset: -25200 };
+        let mut res = rethink::expr(time.clone()).run(&conn).unwrap();
+        assert_eq!(res.next().unwrap().unwrap(), time);
+
+        let options = RunOptions { time_format: Some(Format::Raw), ..Default::default() };
+        let mut res = rethink::expr(time).run_with_options(&conn, &options).unwrap();
+        assert!(matches!(res.next().unwrap().unwrap(), Datum::Object(..)));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let conn = rethink::connect_default().unwrap();
+
+        let data = vec![0x89, b'P', b'N', b'G', 0, 0xff];
+        let mut res = rethink::binary(&data).run(&conn).unwrap();
+        assert_eq!(res.next().unwrap().unwrap(), Datum::Binary(data.clone()));
+
+        let options = RunOptions { binary_format: Some(Format::Raw), ..Default::default() };
+        let mut res = rethink::binary(&data).run_with_options(&conn, &options).unwrap();
+        assert!(matches!(res.next().unwrap().unwrap(), Datum::Object(..)));
+    }
+
+    #[test]
+    fn geometry_round_trip() {
+        use datum::LonLat;
+
+        let conn = rethink::connect_default().unwrap();
+        let point = Datum::Point(LonLat::from((-122.423246, 37.779388)));
+        let mut res = rethink::expr(point.clone()).run(&conn).unwrap();
+        assert_eq!(res.next().unwrap().unwrap(), point);
+
+        let square = vec![(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0)].into_iter().map(LonLat::from).collect::<Vec<_>>();
+        let mut res = rethink::expr(Datum::Polygon(vec![square.clone()])).run(&conn).unwrap();
+        match res.next().unwrap().unwrap() {
+            // The server closes the ring
+            Datum::Polygon(rings) => assert_eq!(rings, vec![square.iter().chain(square.first()).cloned().collect::<Vec<_>>()]),
+            other => panic!("expected a polygon, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn group_count() {
+        let conn = rethink::connect_default().unwrap();
+        let group = ReQL::Term {
+            command: Term_TermType::GROUP,
+            arguments: vec![rethink::expr(Datum::from_str(r#"[{"k": "a"}, {"k": "b"}, {"k": "a"}]"#)), ReQL::string("k")],
+            optional_arguments: HashMap::new()
+        };
+        let count = ReQL::Term {
+            command: Term_TermType::COUNT,
+            arguments: vec![group],
+            optional_arguments: HashMap::new()
+        };
+
+        let mut res = count.run(&conn).unwrap();
+        assert_eq!(res.next().unwrap().unwrap(), Datum::Grouped(vec![
+            (Datum::String("a".to_string()), Datum::Number(Number::UInt(2))),
+            (Datum::String("b".to_string()), Datum::Number(Number::UInt(1))),
+        ]));
+
+        let options = RunOptions { group_format: Some(Format::Raw), ..Default::default() };
+        let mut res = count.run_with_options(&conn, &options).unwrap();
+        assert!(matches!(res.next().unwrap().unwrap(), Datum::Object(..)));
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Thumbnail {
+        id: String,
+        width: u32,
+        labels: Vec<String>,
+    }
+
+    #[test]
+    fn insert_serde_struct() {
+        use serde_datum::from_datum;
+
+        let conn = rethink::connect_default().unwrap();
+
+        let mut rng = rand::thread_rng();
+        let thumbnail = Thumbnail { id: rng.next_u64().to_string(), width: 64, labels: vec!["cat".to_string()] };
+
+        let table_query = rethink::db("test").table("test_table");
+        table_query.insert(&thumbnail, None).unwrap().run(&conn).unwrap();
+        let row = table_query.get(&thumbnail.id).run(&conn).unwrap().next().unwrap().unwrap();
+        assert_eq!(from_datum::<Thumbnail>(row).unwrap(), thumbnail);
+    }
+
+    #[test]
+    fn run_as_typed() {
+        let conn = rethink::connect_default().unwrap();
+
+        let mut rng = rand::thread_rng();
+        let thumbnail = Thumbnail { id: rng.next_u64().to_string(), width: 32, labels: Vec::new() };
+
+        let table_query = rethink::db("test").table("test_table");
+        let written: WriteResult = table_query.insert(&thumbnail, None).unwrap().run_as(&conn).unwrap();
+        assert_eq!(written.inserted, 1);
+        assert_eq!(table_query.get(&thumbnail.id).run_as::<Thumbnail>(&conn).unwrap(), thumbnail);
+        assert_eq!(table_query.get(&thumbnail.id).run_as::<Option<Thumbnail>>(&conn).unwrap().as_ref(), Some(&thumbnail));
+        assert_eq!(table_query.get("no such key").run_as::<Option<Thumbnail>>(&conn).unwrap(), None);
+
+        // Rows which aren't thumbnails fail to decode, naming the field which didn't match
+        let malformed_id = rng.next_u64().to_string();
+        let value = Datum::from_str(&format!(r#"{{"id": "{}", "width": "wide"}}"#, malformed_id));
+        table_query.insert(&value, None).unwrap().run(&conn).unwrap();
+        // Only this test's own rows are read, since other tests share the table
+        let own_rows = ReQL::Term {
+            command: Term_TermType::GET_ALL,
+            arguments: vec![table_query.clone(), ReQL::string(&thumbnail.id), ReQL::string(&malformed_id)],
+            optional_arguments: HashMap::new()
+        };
+        let results = own_rows.run_cursor::<Thumbnail>(&conn).map(|cursor| cursor.collect::<Vec<_>>());
+
+        // Remove the rows again before anything can fail, so they don't break other tests which
+        // read the table
+        let delete = ReQL::Term {
+            command: Term_TermType::DELETE,
+            arguments: vec![own_rows],
+            optional_arguments: HashMap::new()
+        };
+        delete.run(&conn).unwrap();
+
+        let results = results.unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().any(|r| r.as_ref().ok() == Some(&thumbnail)));
+        assert!(results.iter().any(|r| match *r {
+            Err(RunQueryError::DecodeError(ref e)) => e.path_string() == "width",
+            _ => false
+        }));
+    }
+
+    #[test]
+    fn serializes_escaped_names() {
+        let conn = Connection::new("localhost", 28015, Some("quote\"db"), None, 20);
+        assert_eq!(rethink::table("a\\b").serialize_query_for_connection(&conn).unwrap(),
+                   r##"[1,[15,["a\\b"]],{"db":[14,["quote\"db"]]}]"##);
+
+        let mut optional_arguments = HashMap::new();
+        optional_arguments.insert("bad\"name".to_string(), Datum::Bool(true));
+        let term = ReQL::Term {
+            command: Term_TermType::INSERT,
+            arguments: vec![rethink::table("t")],
+            optional_arguments: optional_arguments
+        };
+        assert_eq!(term.serialize().unwrap(), r##"[56,[[15,["t"]]],{"bad\"name":true}]"##);
+    }
+
+    #[test]
+    fn serializes_run_options() {
+        let conn = Connection::new("localhost", 28015, Some("default_db_name"), None, 20);
+        let options = RunOptions {
+            read_mode: Some(ReadMode::Outdated),
+            durability: Some(Durability::Soft),
+            profile: Some(true),
+            array_limit: Some(200000),
+            time_format: Some(Format::Raw),
+            first_batch_scaleback_factor: Some(0.5),
+            ..Default::default()
+        };
+        assert_eq!(conn.serialize_params_with(&options.optargs().unwrap()),
+                   r##"{"db":[14,["default_db_name"]],"read_mode":"outdated","durability":"soft","profile":true,"array_limit":200000,"time_format":"raw","first_batch_scaleback_factor":0.5}"##);
+        assert_eq!(conn.serialize_params_with(&RunOptions::default().optargs().unwrap()),
+                   r##"{"db":[14,["default_db_name"]]}"##);
+    }
+
+    #[test]
+    fn run_with_options() {
+        let conn = rethink::connect_default().unwrap();
+        let options = RunOptions {
+            read_mode: Some(ReadMode::Majority),
+            min_batch_rows: Some(1),
+            max_batch_bytes: Some(1024),
+            ..Default::default()
+        };
+        let mut res = rethink::expr(Datum::String("foo".to_string())).run_with_options(&conn, &options).unwrap();
+        assert_eq!(res.next().unwrap().unwrap(), Datum::String("foo".to_string()));
+    }
+
+    #[test]
+    fn profile_query() {
+        let conn = rethink::connect_default().unwrap();
+        let options = RunOptions { profile: Some(true), ..Default::default() };
+        let res = rethink::db("test").table("test_table").run_with_options(&conn, &options).unwrap();
+        let profile = res.profile().unwrap();
+        assert!(!profile.events.is_empty());
+        assert!(!profile.hotspots().is_empty());
+
+        let res = rethink::db("test").table("test_table").run(&conn).unwrap();
+        assert!(res.profile().is_none());
+    }
+
+    #[test]
+    fn changefeed_notes() {
+        let conn = rethink::connect_default().unwrap();
+
+        let changes = ReQL::Term {
+            command: Term_TermType::CHANGES,
+            arguments: vec![rethink::db("test").table("test_table")],
+            optional_arguments: HashMap::new()
+        };
+        let mut feed = changes.run(&conn).unwrap();
+        assert!(feed.is_feed());
+        assert_eq!(feed.feed_kind(), Some(Response_ResponseNote::SEQUENCE_FEED));
+        assert!(!feed.is_complete());
+        feed.close().unwrap();
+
+        let res = rethink::db("test").table("test_table").run(&conn).unwrap();
+        assert!(!res.is_feed());
+        assert!(res.notes().is_empty());
+    }
+
+    #[test]
+    fn cursor_reads_every_batch() {
+        let mut conn = rethink::connect_default().unwrap();
+
+        let mut cursor = rethink::db("test").table("test_table").run(&mut conn).unwrap();
+        let rows = cursor.by_ref().collect::<Result<Vec<_>, _>>().unwrap();
+        assert!(cursor.is_complete());
+        assert!(rows.iter().all(|row| matches!(row, &Datum::Object(..))));
+    }
+
+    #[test]
+    fn cursor_close_stops_query() {
+        let mut conn = rethink::connect_default().unwrap();
+
+        let mut cursor = rethink::db("test").table("test_table").run(&mut conn).unwrap();
+        cursor.close().unwrap();
+        assert!(cursor.is_complete());
+        drop(cursor);
+
+        // The connection is still usable after the query was stopped
+        let res = rethink::db_list().run(&mut conn).unwrap();
+        assert_eq!(res.response_type(), Response_ResponseType::SUCCESS_ATOM);
+    }
+
+    #[test]
+    fn run_async() {
+        let mut core = Core::new().unwrap();
+        let conn = core.run(rethink::connect_async("localhost", 28015, None, None, &core.handle())).unwrap();
+
+        let atom = core.run(rethink::expr(Datum::String("foo".to_string())).run_async(&conn)).unwrap();
+        assert!(matches!(atom, AsyncResponse::Atom(Datum::String(ref s)) if s == "foo"));
+
+        let table = core.run(rethink::db("test").table("test_table").run_async(&conn)).unwrap();
+        match table {
+            AsyncResponse::Stream(cursor) => {
+                let rows = core.run(cursor.collect()).unwrap();
+                assert!(rows.iter().all(|row| matches!(row, &Datum::Object(..))));
+            },
+            AsyncResponse::Atom(..) => panic!("Expected a stream of rows")
+        }
+    }
+
+    #[test]
+    fn concurrent_queries_share_connection() {
+        let conn = Arc::new(rethink::connect_default().unwrap());
+
+        let handles = (0..8).map(|i| {
+            let conn = conn.clone();
+            thread::spawn(move || {
+                let expected = format!("thread {}", i);
+                let mut res = rethink::expr(Datum::String(expected.clone())).run(&conn).unwrap();
+                assert_eq!(res.next().unwrap().unwrap(), Datum::String(expected));
+            })
+        }).collect::<Vec<_>>();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
     }
 }
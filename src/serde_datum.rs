@@ -0,0 +1,760 @@
+use std::collections::hash_map::{self, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::vec;
+
+extern crate serde;
+use self::serde::de::{self, Deserialize, DeserializeOwned, DeserializeSeed, Deserializer, IntoDeserializer,
+                      MapAccess, SeqAccess, Visitor};
+use self::serde::ser::{self, Serialize, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant,
+                       SerializeTuple, SerializeTupleStruct, SerializeTupleVariant, Serializer};
+
+use datum::{Datum, DecodeOptions, Number, from_object};
+
+/// An error converting between a `Datum` and a Rust type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatumError {
+    pub message: String,
+    /// Where in the datum the error happened, outermost first
+    pub path: Vec<PathSegment>,
+}
+
+/// One step into a nested datum.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathSegment {
+    /// An object key, or the name of an enum variant
+    Field(String),
+    /// An array index
+    Index(usize),
+}
+
+impl DatumError {
+    fn new(message: String) -> DatumError {
+        DatumError { message: message, path: Vec::new() }
+    }
+
+    // Errors are built innermost first, so each level puts its step in front
+    fn at(mut self, segment: PathSegment) -> DatumError {
+        self.path.insert(0, segment);
+        self
+    }
+
+    /// The path as it would be written in Rust or JavaScript, such as `roles[1].since`.
+    pub fn path_string(&self) -> String {
+        let mut path = String::new();
+        for segment in &self.path {
+            match *segment {
+                PathSegment::Field(ref name) => {
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(name);
+                },
+                PathSegment::Index(i) => path.push_str(&format!("[{}]", i))
+            }
+        }
+        path
+    }
+}
+
+impl fmt::Display for DatumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{}: {}", self.path_string(), self.message)
+        }
+    }
+}
+
+impl Error for DatumError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl ser::Error for DatumError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DatumError::new(msg.to_string())
+    }
+}
+
+impl de::Error for DatumError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DatumError::new(msg.to_string())
+    }
+}
+
+/// Converts any value which implements `Serialize` into a `Datum`.
+///
+/// Structs and maps become objects, sequences and tuples become arrays, and byte buffers become
+/// binary data. Objects shaped like a pseudo-type, such as a serialized `Datum::Time`, become
+/// that pseudo-type again.
+pub fn to_datum<T: Serialize + ?Sized>(value: &T) -> Result<Datum, DatumError> {
+    value.serialize(DatumSerializer)
+}
+
+/// Converts a `Datum` into any type which implements `Deserialize`.
+pub fn from_datum<T: DeserializeOwned>(datum: Datum) -> Result<T, DatumError> {
+    T::deserialize(datum)
+}
+
+// Pseudo-types are written out as the objects the server represents them as
+impl Serialize for Datum {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Datum::Null => serializer.serialize_unit(),
+            Datum::Bool(b) => serializer.serialize_bool(b),
+            Datum::String(ref s) => serializer.serialize_str(s),
+            Datum::Number(Number::Int(n)) => serializer.serialize_i64(n),
+            Datum::Number(Number::UInt(n)) => serializer.serialize_u64(n),
+            Datum::Number(Number::Float(n)) => serializer.serialize_f64(n),
+            Datum::Array(ref a) => {
+                let mut seq = try!(serializer.serialize_seq(Some(a.len())));
+                for element in a {
+                    try!(seq.serialize_element(element));
+                }
+                seq.end()
+            },
+            Datum::Object(ref m) => {
+                let mut map = try!(serializer.serialize_map(Some(m.len())));
+                for (k, v) in m {
+                    try!(map.serialize_entry(k, v));
+                }
+                map.end()
+            },
+            _ => Serialize::serialize(&self.pseudo_type().unwrap(), serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Datum {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Datum, D::Error> {
+        deserializer.deserialize_any(DatumVisitor)
+    }
+}
+
+struct DatumVisitor;
+
+impl<'de> Visitor<'de> for DatumVisitor {
+    type Value = Datum;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any value that can be stored in RethinkDB")
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Datum, E> {
+        Ok(Datum::Bool(b))
+    }
+
+    fn visit_i64<E>(self, n: i64) -> Result<Datum, E> {
+        Ok(Datum::Number(Number::Int(n)))
+    }
+
+    fn visit_u64<E>(self, n: u64) -> Result<Datum, E> {
+        Ok(Datum::Number(Number::UInt(n)))
+    }
+
+    fn visit_f64<E>(self, n: f64) -> Result<Datum, E> {
+        Ok(Datum::Number(Number::Float(n)))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Datum, E> {
+        Ok(Datum::String(s.to_string()))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<Datum, E> {
+        Ok(Datum::String(s))
+    }
+
+    fn visit_bytes<E>(self, b: &[u8]) -> Result<Datum, E> {
+        Ok(Datum::Binary(b.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, b: Vec<u8>) -> Result<Datum, E> {
+        Ok(Datum::Binary(b))
+    }
+
+    fn visit_unit<E>(self) -> Result<Datum, E> {
+        Ok(Datum::Null)
+    }
+
+    fn visit_none<E>(self) -> Result<Datum, E> {
+        Ok(Datum::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Datum, D::Error> {
+        Datum::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Datum, A::Error> {
+        let mut elements = Vec::new();
+        while let Some(element) = try!(seq.next_element()) {
+            elements.push(element);
+        }
+        Ok(Datum::Array(elements))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Datum, A::Error> {
+        let mut object = HashMap::new();
+        while let Some((k, v)) = try!(map.next_entry()) {
+            object.insert(k, v);
+        }
+        Ok(from_object(object, &DecodeOptions::default()))
+    }
+}
+
+struct DatumSerializer;
+
+impl Serializer for DatumSerializer {
+    type Ok = Datum;
+    type Error = DatumError;
+
+    type SerializeSeq = SerializeArray;
+    type SerializeTuple = SerializeArray;
+    type SerializeTupleStruct = SerializeArray;
+    type SerializeTupleVariant = SerializeVariant<SerializeArray>;
+    type SerializeMap = SerializeObject;
+    type SerializeStruct = SerializeObject;
+    type SerializeStructVariant = SerializeVariant<SerializeObject>;
+
+    fn serialize_bool(self, v: bool) -> Result<Datum, DatumError> {
+        Ok(Datum::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Datum, DatumError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Datum, DatumError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Datum, DatumError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Datum, DatumError> {
+        Ok(Datum::Number(Number::Int(v)))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Datum, DatumError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Datum, DatumError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Datum, DatumError> {
+        self.serialize_u64(v as u64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Datum, DatumError> {
+        Ok(Datum::Number(Number::UInt(v)))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Datum, DatumError> {
+        self.serialize_f64(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Datum, DatumError> {
+        Ok(Datum::Number(Number::Float(v)))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Datum, DatumError> {
+        Ok(Datum::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Datum, DatumError> {
+        Ok(Datum::String(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Datum, DatumError> {
+        Ok(Datum::Binary(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Datum, DatumError> {
+        Ok(Datum::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Datum, DatumError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Datum, DatumError> {
+        Ok(Datum::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Datum, DatumError> {
+        Ok(Datum::Null)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> Result<Datum, DatumError> {
+        Ok(Datum::String(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<Datum, DatumError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32, variant: &'static str,
+                                                        value: &T) -> Result<Datum, DatumError> {
+        let mut object = HashMap::new();
+        object.insert(variant.to_string(), try!(to_datum(value)));
+        Ok(Datum::Object(object))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SerializeArray, DatumError> {
+        Ok(SerializeArray { elements: Vec::with_capacity(len.unwrap_or(0)) })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SerializeArray, DatumError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SerializeArray, DatumError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str,
+                               len: usize) -> Result<SerializeVariant<SerializeArray>, DatumError> {
+        Ok(SerializeVariant { variant: variant, inner: try!(self.serialize_seq(Some(len))) })
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<SerializeObject, DatumError> {
+        Ok(SerializeObject { object: HashMap::new(), key: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<SerializeObject, DatumError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str,
+                                len: usize) -> Result<SerializeVariant<SerializeObject>, DatumError> {
+        Ok(SerializeVariant { variant: variant, inner: try!(self.serialize_map(Some(len))) })
+    }
+}
+
+struct SerializeArray {
+    elements: Vec<Datum>,
+}
+
+impl SerializeSeq for SerializeArray {
+    type Ok = Datum;
+    type Error = DatumError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), DatumError> {
+        self.elements.push(try!(to_datum(value)));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Datum, DatumError> {
+        Ok(Datum::Array(self.elements))
+    }
+}
+
+impl SerializeTuple for SerializeArray {
+    type Ok = Datum;
+    type Error = DatumError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), DatumError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Datum, DatumError> {
+        SerializeSeq::end(self)
+    }
+}
+
+impl SerializeTupleStruct for SerializeArray {
+    type Ok = Datum;
+    type Error = DatumError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), DatumError> {
+        SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Datum, DatumError> {
+        SerializeSeq::end(self)
+    }
+}
+
+struct SerializeObject {
+    object: HashMap<String, Datum>,
+    // The key of the entry being serialized, between serialize_key and serialize_value
+    key: Option<String>,
+}
+
+impl SerializeMap for SerializeObject {
+    type Ok = Datum;
+    type Error = DatumError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), DatumError> {
+        self.key = Some(match try!(to_datum(key)) {
+            Datum::String(key) => key,
+            // Like JSON, objects can only have strings as keys
+            Datum::Number(n) => n.to_string(),
+            Datum::Bool(b) => b.to_string(),
+            other => return Err(DatumError::new(format!("Object keys must be strings, not {:?}", other)))
+        });
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), DatumError> {
+        let key = try!(self.key.take().ok_or_else(|| {
+            DatumError::new("serialize_value called before serialize_key".to_string())
+        }));
+        self.object.insert(key, try!(to_datum(value)));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Datum, DatumError> {
+        Ok(from_object(self.object, &DecodeOptions::default()))
+    }
+}
+
+impl SerializeStruct for SerializeObject {
+    type Ok = Datum;
+    type Error = DatumError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), DatumError> {
+        self.object.insert(key.to_string(), try!(to_datum(value)));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Datum, DatumError> {
+        Ok(from_object(self.object, &DecodeOptions::default()))
+    }
+}
+
+// An enum variant with data, which is written as an object with the variant name as its only key
+struct SerializeVariant<S> {
+    variant: &'static str,
+    inner: S,
+}
+
+impl<S> SerializeVariant<S> {
+    fn wrap(variant: &'static str, datum: Datum) -> Datum {
+        let mut object = HashMap::new();
+        object.insert(variant.to_string(), datum);
+        Datum::Object(object)
+    }
+}
+
+impl SerializeTupleVariant for SerializeVariant<SerializeArray> {
+    type Ok = Datum;
+    type Error = DatumError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), DatumError> {
+        SerializeSeq::serialize_element(&mut self.inner, value)
+    }
+
+    fn end(self) -> Result<Datum, DatumError> {
+        Ok(Self::wrap(self.variant, try!(SerializeSeq::end(self.inner))))
+    }
+}
+
+impl SerializeStructVariant for SerializeVariant<SerializeObject> {
+    type Ok = Datum;
+    type Error = DatumError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<(), DatumError> {
+        SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn end(self) -> Result<Datum, DatumError> {
+        Ok(Self::wrap(self.variant, try!(SerializeStruct::end(self.inner))))
+    }
+}
+
+impl<'de> Deserializer<'de> for Datum {
+    type Error = DatumError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DatumError> {
+        match self {
+            Datum::Null => visitor.visit_unit(),
+            Datum::Bool(b) => visitor.visit_bool(b),
+            Datum::String(s) => visitor.visit_string(s),
+            // Whole numbers are offered as integers, so they can be read into integer fields
+            Datum::Number(n) => match (n.as_i64(), n.as_u64()) {
+                (Some(i), _) => visitor.visit_i64(i),
+                (None, Some(u)) => visitor.visit_u64(u),
+                (None, None) => visitor.visit_f64(n.as_f64())
+            },
+            Datum::Array(a) => visitor.visit_seq(ArrayAccess { elements: a.into_iter(), index: 0 }),
+            Datum::Object(m) => visitor.visit_map(ObjectAccess { entries: m.into_iter(), key: None, value: None }),
+            Datum::Binary(b) => visitor.visit_byte_buf(b),
+            other => other.pseudo_type().unwrap().deserialize_any(visitor)
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, DatumError> {
+        match self {
+            Datum::Null => visitor.visit_none(),
+            other => visitor.visit_some(other)
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, DatumError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str],
+                                         visitor: V) -> Result<V::Value, DatumError> {
+        match self {
+            Datum::String(variant) => visitor.visit_enum(EnumAccess { variant: variant, value: None }),
+            Datum::Object(m) => {
+                if m.len() != 1 {
+                    return Err(de::Error::custom("expected an object with a single key for an enum variant"));
+                }
+                let (variant, value) = m.into_iter().next().unwrap();
+                visitor.visit_enum(EnumAccess { variant: variant, value: Some(value) })
+            },
+            other => Err(de::Error::custom(format!("expected a string or an object for an enum variant, got {:?}", other)))
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf unit
+        unit_struct seq tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, DatumError> for Datum {
+    type Deserializer = Datum;
+
+    fn into_deserializer(self) -> Datum {
+        self
+    }
+}
+
+struct ArrayAccess {
+    elements: vec::IntoIter<Datum>,
+    // The index of the next element
+    index: usize,
+}
+
+impl<'de> SeqAccess<'de> for ArrayAccess {
+    type Error = DatumError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, DatumError> {
+        match self.elements.next() {
+            Some(element) => {
+                let index = self.index;
+                self.index += 1;
+                seed.deserialize(element).map(Some).map_err(|e| e.at(PathSegment::Index(index)))
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.elements.len())
+    }
+}
+
+struct ObjectAccess {
+    entries: hash_map::IntoIter<String, Datum>,
+    // The entry whose key was just read
+    key: Option<String>,
+    value: Option<Datum>,
+}
+
+impl<'de> MapAccess<'de> for ObjectAccess {
+    type Error = DatumError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, DatumError> {
+        match self.entries.next() {
+            Some((k, v)) => {
+                self.key = Some(k.clone());
+                self.value = Some(v);
+                seed.deserialize(k.into_deserializer()).map(Some)
+            },
+            None => Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, DatumError> {
+        match self.value.take() {
+            Some(value) => {
+                let key = self.key.take().unwrap_or_default();
+                seed.deserialize(value).map_err(|e| e.at(PathSegment::Field(key)))
+            },
+            None => Err(de::Error::custom("next_value called before next_key"))
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct EnumAccess {
+    variant: String,
+    value: Option<Datum>,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess {
+    type Error = DatumError;
+    type Variant = VariantAccess;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, VariantAccess), DatumError> {
+        let name = self.variant.clone();
+        let variant = try!(seed.deserialize(self.variant.into_deserializer()));
+        Ok((variant, VariantAccess { name: name, value: self.value }))
+    }
+}
+
+struct VariantAccess {
+    name: String,
+    value: Option<Datum>,
+}
+
+impl<'de> de::VariantAccess<'de> for VariantAccess {
+    type Error = DatumError;
+
+    fn unit_variant(self) -> Result<(), DatumError> {
+        match self.value {
+            None | Some(Datum::Null) => Ok(()),
+            Some(other) => Err(de::Error::custom(format!("expected no data for a unit variant, got {:?}", other)))
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, DatumError> {
+        let name = self.name;
+        seed.deserialize(self.value.unwrap_or(Datum::Null)).map_err(|e| e.at(PathSegment::Field(name)))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, DatumError> {
+        let name = self.name;
+        self.value.unwrap_or(Datum::Null).deserialize_seq(visitor).map_err(|e| e.at(PathSegment::Field(name)))
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value, DatumError> {
+        let name = self.name;
+        self.value.unwrap_or(Datum::Null).deserialize_map(visitor).map_err(|e| e.at(PathSegment::Field(name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datum::LonLat;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Role {
+        Admin,
+        Member { since: u32 },
+        Guest(String),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        id: String,
+        age: u8,
+        score: f64,
+        tags: Vec<String>,
+        nickname: Option<String>,
+        roles: Vec<Role>,
+        home: Datum,
+    }
+
+    struct Bytes(Vec<u8>);
+
+    impl Serialize for Bytes {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+
+    fn user() -> User {
+        User {
+            id: "u1".to_string(),
+            age: 42,
+            score: 0.5,
+            tags: vec!["a".to_string(), "b".to_string()],
+            nickname: None,
+            roles: vec![Role::Admin, Role::Member { since: 2015 }, Role::Guest("x".to_string())],
+            home: Datum::Point(LonLat { longitude: 1.0, latitude: 2.0 }),
+        }
+    }
+
+    #[test]
+    fn struct_round_trips() {
+        let datum = to_datum(&user()).unwrap();
+        match datum {
+            Datum::Object(ref o) => {
+                assert_eq!(o["age"], Datum::Number(Number::UInt(42)));
+                assert_eq!(o["nickname"], Datum::Null);
+                assert_eq!(o["home"], Datum::Point(LonLat { longitude: 1.0, latitude: 2.0 }));
+                assert_eq!(o["roles"], Datum::from_str(r#"["Admin", {"Member": {"since": 2015}}, {"Guest": "x"}]"#));
+            },
+            ref other => panic!("expected an object, got {:?}", other)
+        }
+        assert_eq!(from_datum::<User>(datum).unwrap(), user());
+    }
+
+    #[test]
+    fn datum_round_trips() {
+        let time = Datum::Time { epoch_time: 1500000000.5, utc_offset: 3600 };
+        assert_eq!(to_datum(&time).unwrap(), time);
+        assert_eq!(from_datum::<Datum>(time.clone()).unwrap(), time);
+
+        assert_eq!(to_datum(&Bytes(vec![0, 1, 2])).unwrap(), Datum::Binary(vec![0, 1, 2]));
+
+        let object = Datum::from_str(r#"{"a": [1, null, "b"], "c": {"d": true}}"#);
+        assert_eq!(to_datum(&object).unwrap(), object);
+        assert_eq!(from_datum::<Datum>(object.clone()).unwrap(), object);
+    }
+
+    #[derive(Serialize)]
+    struct Time {
+        #[serde(rename = "$reql_type$")]
+        reql_type: &'static str,
+        epoch_time: f64,
+        timezone: &'static str,
+    }
+
+    #[test]
+    fn structs_and_maps_decode_pseudo_types_alike() {
+        let time = Time { reql_type: "TIME", epoch_time: 1500000000.5, timezone: "+01:00" };
+        let mut map = HashMap::new();
+        map.insert("$reql_type$", to_datum(&time.reql_type).unwrap());
+        map.insert("epoch_time", to_datum(&time.epoch_time).unwrap());
+        map.insert("timezone", to_datum(&time.timezone).unwrap());
+
+        let expected = Datum::Time { epoch_time: 1500000000.5, utc_offset: 3600 };
+        assert_eq!(to_datum(&time).unwrap(), expected);
+        assert_eq!(to_datum(&map).unwrap(), expected);
+    }
+
+    #[test]
+    fn large_integers_round_trip() {
+        let n = u64::max_value() - 1;
+        assert_eq!(to_datum(&n).unwrap(), Datum::Number(Number::UInt(n)));
+        assert_eq!(from_datum::<u64>(Datum::Number(Number::UInt(n))).unwrap(), n);
+        assert_eq!(from_datum::<i64>(Datum::Number(Number::Int(i64::min_value()))).unwrap(), i64::min_value());
+    }
+
+    #[test]
+    fn reports_mismatched_types() {
+        assert!(from_datum::<u8>(Datum::Number(Number::Float(0.5))).is_err());
+        assert!(from_datum::<u8>(Datum::Number(Number::UInt(300))).is_err());
+        assert!(from_datum::<u64>(Datum::Number(Number::Int(-1))).is_err());
+        assert!(from_datum::<String>(Datum::Null).is_err());
+        assert!(from_datum::<User>(Datum::from_str(r#"{"id": "u1"}"#)).is_err());
+
+        let mut datum = to_datum(&user()).unwrap();
+        if let Datum::Object(ref mut o) = datum {
+            o.insert("roles".to_string(), Datum::from_str(r#"["Admin", {"Member": {"since": "2015"}}]"#));
+        }
+        let error = from_datum::<User>(datum).unwrap_err();
+        assert_eq!(error.path_string(), "roles[1].Member.since");
+        assert!(error.to_string().starts_with("roles[1].Member.since: invalid type"));
+
+        let mut map = HashMap::new();
+        map.insert(vec![1], true);
+        assert!(to_datum(&map).is_err());
+    }
+}
@@ -0,0 +1,331 @@
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+extern crate rustls;
+use self::rustls::{ClientConfig, ClientSession, Session};
+use self::rustls::internal::pemfile;
+use self::rustls::sign;
+
+extern crate webpki;
+extern crate webpki_roots;
+
+use connection::ConnectionError;
+
+/// Options for an encrypted connection.
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// A PEM file of CA certificates to trust, instead of the standard web roots
+    pub ca_file: Option<PathBuf>,
+    /// PEM files holding a client certificate chain and its private key
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+    /// The name the server's certificate is verified against, when it differs from the host
+    pub server_name: Option<String>,
+}
+
+pub(crate) fn client_session(host: &str, options: &TlsOptions) -> Result<ClientSession, ConnectionError> {
+    let config = Arc::new(try!(client_config(options)));
+    let server_name = options.server_name.as_ref().map(|name| &name[..]).unwrap_or(host);
+    let dns_name = try!(webpki::DNSNameRef::try_from_ascii_str(server_name).map_err(|_| {
+        ConnectionError::TlsError(format!("\"{}\" is not a valid server name", server_name))
+    }));
+    Ok(ClientSession::new(&config, dns_name))
+}
+
+fn client_config(options: &TlsOptions) -> Result<ClientConfig, ConnectionError> {
+    let mut config = ClientConfig::new();
+
+    match options.ca_file {
+        Some(ref path) => {
+            let mut reader = try!(open(path));
+            let (valid, _) = try!(config.root_store.add_pem_file(&mut reader).map_err(|_| {
+                ConnectionError::TlsError(format!("Could not parse CA certificates in {}", path.display()))
+            }));
+            if valid == 0 {
+                return Err(ConnectionError::TlsError(
+                    format!("No CA certificates found in {}", path.display())));
+            }
+        },
+        None => config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS)
+    }
+
+    if let Some((ref cert_path, ref key_path)) = options.client_cert {
+        let certs = try!(read_pem(cert_path, pemfile::certs));
+        let mut keys = try!(read_pem(key_path, pemfile::pkcs8_private_keys));
+        if keys.is_empty() {
+            keys = try!(read_pem(key_path, pemfile::rsa_private_keys));
+        }
+        let key = try!(keys.pop().ok_or_else(|| {
+            ConnectionError::TlsError(format!("No private key found in {}", key_path.display()))
+        }));
+        // set_single_client_cert panics on a key it can't sign with
+        try!(sign::any_supported_type(&key).map_err(|_| {
+            ConnectionError::TlsError(format!("Unsupported private key in {}", key_path.display()))
+        }));
+        config.set_single_client_cert(certs, key);
+    }
+
+    Ok(config)
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, ConnectionError> {
+    File::open(path).map(BufReader::new).map_err(|e| {
+        ConnectionError::TlsError(format!("Could not open {}: {}", path.display(), e))
+    })
+}
+
+fn read_pem<T, F>(path: &Path, parse: F) -> Result<Vec<T>, ConnectionError>
+    where F: Fn(&mut BufRead) -> Result<Vec<T>, ()> {
+    let mut reader = try!(open(path));
+    parse(&mut reader).map_err(|_| {
+        ConnectionError::TlsError(format!("Could not parse {}", path.display()))
+    })
+}
+
+fn tls_io_error(e: rustls::TLSError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+// Write out anything the session has queued, such as encrypted data or alerts
+fn flush_tls(session: &mut ClientSession, mut socket: &TcpStream) -> io::Result<()> {
+    while session.wants_write() {
+        try!(session.write_tls(&mut socket));
+    }
+    Ok(())
+}
+
+/// The reading half of an encrypted connection.
+///
+/// The session is only locked while processing data, never while waiting on the socket, so the
+/// writing half can send queries while this half is blocked waiting for a response.
+pub struct TlsReader {
+    session: Arc<Mutex<ClientSession>>,
+    socket: TcpStream,
+}
+
+/// The writing half of an encrypted connection.
+pub struct TlsWriter {
+    session: Arc<Mutex<ClientSession>>,
+    socket: TcpStream,
+}
+
+// Split an established session into halves which can be used from different threads
+pub(crate) fn split(session: ClientSession, socket: &TcpStream) -> io::Result<(TlsReader, TlsWriter)> {
+    let session = Arc::new(Mutex::new(session));
+    Ok((TlsReader { session: session.clone(), socket: try!(socket.try_clone()) },
+        TlsWriter { session: session, socket: try!(socket.try_clone()) }))
+}
+
+impl Read for TlsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut incoming = [0u8; 4096];
+        loop {
+            {
+                let mut session = self.session.lock().unwrap();
+                let n = try!(session.read(buf));
+                if n > 0 {
+                    return Ok(n);
+                }
+            }
+
+            let received = try!((&self.socket).read(&mut incoming));
+            if received == 0 {
+                return Ok(0);
+            }
+
+            let mut session = self.session.lock().unwrap();
+            try!(session.read_tls(&mut &incoming[..received]));
+            try!(session.process_new_packets().map_err(tls_io_error));
+            try!(flush_tls(&mut session, &self.socket));
+        }
+    }
+}
+
+impl Write for TlsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut session = self.session.lock().unwrap();
+        let n = try!(session.write(buf));
+        try!(flush_tls(&mut session, &self.socket));
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut session = self.session.lock().unwrap();
+        try!(session.flush());
+        flush_tls(&mut session, &self.socket)
+    }
+}
+
+#[test]
+fn missing_ca_file() {
+    let options = TlsOptions {
+        ca_file: Some(PathBuf::from("/nonexistent/ca.pem")),
+        ..Default::default()
+    };
+    assert!(matches!(client_session("localhost", &options), Err(ConnectionError::TlsError(..))));
+}
+
+#[test]
+fn invalid_server_name() {
+    let options = TlsOptions {
+        server_name: Some("not a hostname".to_string()),
+        ..Default::default()
+    };
+    assert!(matches!(client_session("localhost", &options), Err(ConnectionError::TlsError(..))));
+}
+
+// Certificates for these tests are in testdata/tls: a CA, a certificate it signed for
+// "localhost", and a client certificate it signed
+#[cfg(test)]
+fn test_file(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/tls").join(name)
+}
+
+#[cfg(test)]
+fn server_config(require_client_cert: bool) -> Arc<rustls::ServerConfig> {
+    use self::rustls::{AllowAnyAuthenticatedClient, NoClientAuth, RootCertStore, ServerConfig};
+
+    let certs = read_pem(&test_file("server.pem"), pemfile::certs).unwrap();
+    let key = read_pem(&test_file("server.key"), pemfile::pkcs8_private_keys).unwrap().pop().unwrap();
+    let mut config = if require_client_cert {
+        let mut roots = RootCertStore::empty();
+        roots.add_pem_file(&mut open(&test_file("ca.pem")).unwrap()).unwrap();
+        ServerConfig::new(AllowAnyAuthenticatedClient::new(roots))
+    } else {
+        ServerConfig::new(NoClientAuth::new())
+    };
+    config.set_single_cert(certs, key).unwrap();
+    Arc::new(config)
+}
+
+// A TLS server which answers "ping" with "pong" on one connection, optionally only accepting
+// clients with a certificate signed by the test CA
+#[cfg(test)]
+fn start_tls_server(require_client_cert: bool) -> u16 {
+    use std::net::TcpListener;
+    use std::thread;
+    use self::rustls::ServerSession;
+
+    let config = server_config(require_client_cert);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        let mut session = ServerSession::new(&config);
+        let mut stream = rustls::Stream::new(&mut session, &mut socket);
+        let mut ping = [0u8; 4];
+        if stream.read_exact(&mut ping).is_ok() {
+            let _ = stream.write_all(b"pong");
+        }
+    });
+    port
+}
+
+// A server standing in for RethinkDB behind TLS. It accepts one connection with the password
+// "secret", then answers each query with the query's own term, until the client hangs up.
+#[cfg(test)]
+fn start_tls_rethink_server() -> u16 {
+    use std::net::TcpListener;
+    use std::thread;
+    use self::rustls::ServerSession;
+    use connection::echo_queries;
+    use handshake;
+
+    let config = server_config(false);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        let (mut socket, _) = listener.accept().unwrap();
+        let mut session = ServerSession::new(&config);
+        let mut stream = rustls::Stream::new(&mut session, &mut socket);
+        handshake::accept(&mut stream, "secret").unwrap();
+        echo_queries(&mut stream);
+    });
+    port
+}
+
+#[cfg(test)]
+fn ping(port: u16, options: &TlsOptions) -> Result<[u8; 4], ConnectionError> {
+    let mut session = try!(client_session("localhost", options));
+    let mut socket = try!(TcpStream::connect(("127.0.0.1", port)));
+    let mut stream = rustls::Stream::new(&mut session, &mut socket);
+    try!(stream.write_all(b"ping"));
+    let mut pong = [0u8; 4];
+    try!(stream.read_exact(&mut pong));
+    Ok(pong)
+}
+
+#[test]
+fn trusts_custom_ca() {
+    let port = start_tls_server(false);
+    let options = TlsOptions {
+        ca_file: Some(test_file("ca.pem")),
+        ..Default::default()
+    };
+    assert_eq!(&ping(port, &options).unwrap(), b"pong");
+}
+
+#[test]
+fn rejects_untrusted_certificate() {
+    // The test CA isn't one of the standard web roots
+    let port = start_tls_server(false);
+    assert!(matches!(ping(port, &TlsOptions::default()), Err(ConnectionError::TlsError(..))));
+}
+
+#[test]
+fn verifies_server_name() {
+    let port = start_tls_server(false);
+    let options = TlsOptions {
+        ca_file: Some(test_file("ca.pem")),
+        server_name: Some("rethinkdb.example.com".to_string()),
+        ..Default::default()
+    };
+    assert!(matches!(ping(port, &options), Err(ConnectionError::TlsError(..))));
+}
+
+#[test]
+fn sends_client_certificate() {
+    let port = start_tls_server(true);
+    let options = TlsOptions {
+        ca_file: Some(test_file("ca.pem")),
+        client_cert: Some((test_file("client.pem"), test_file("client.key"))),
+        ..Default::default()
+    };
+    assert_eq!(&ping(port, &options).unwrap(), b"pong");
+
+    // The server turns away clients without one
+    let port = start_tls_server(true);
+    let options = TlsOptions { client_cert: None, ..options };
+    assert!(ping(port, &options).is_err());
+}
+
+#[test]
+fn runs_queries_over_tls() {
+    use std::thread;
+    use datum::Datum;
+    use rethink::rethink;
+
+    let port = start_tls_rethink_server();
+    let options = TlsOptions {
+        ca_file: Some(test_file("ca.pem")),
+        ..Default::default()
+    };
+    let conn = Arc::new(rethink::connect_tls("localhost", port, None, "admin", "secret", options, 5).unwrap());
+
+    // Queries from several threads share the session, while the reader thread waits on it
+    let handles = (0..4).map(|i| {
+        let conn = conn.clone();
+        thread::spawn(move || {
+            let expected = Datum::String(format!("thread {}", i));
+            let mut res = rethink::expr(expected.clone()).run(&conn).unwrap();
+            assert_eq!(res.next().unwrap().unwrap(), expected);
+        })
+    }).collect::<Vec<_>>();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}